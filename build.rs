@@ -1,9 +1,11 @@
 #[allow(unused_must_use)]
-
 fn main() {
     // Set environment variable PYO3_NO_PYTHON
     // to disable the automatic detection of Python on host.
     // (this made cross-building easier)
     std::env::set_var("PYO3_NO_PYTHON", "1");
-    pyo3_build_config::add_extension_module_link_args();
+    // the python bindings are only built with the std feature
+    if std::env::var_os("CARGO_FEATURE_STD").is_some() {
+        pyo3_build_config::add_extension_module_link_args();
+    }
 }
\ No newline at end of file
@@ -36,7 +36,8 @@ function build_target()
 
     # Very important to clean, incase old crates for x86 are present
     cargo clean
-    cross build --target $CROSSTARGET --release
+    # the python extension is a cdylib, which the crate doesn't build by default
+    cross rustc --lib --target $CROSSTARGET --release --crate-type cdylib
 
     # Remove old python builds
     rm -rf build 
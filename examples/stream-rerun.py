@@ -16,11 +16,6 @@ dev = Lidar()
 dev.open("/dev/ttyUSB0")
 
 
-def to_3D(points: List[Tuple[float,float]]) -> np.ndarray:
-    zz = np.zeros((len(points), 1))
-    return np.hstack([points, zz])
-
-
 while dev.alive():
     # read a measurement frame
     try:
@@ -32,8 +27,8 @@ while dev.alive():
     # set the time of this data
     rr.set_time_nanos("scan", scan.timestamp)
 
-    # convert the 2D points to 3D
-    points_xyz = to_3D(scan.points)
+    # get the points as an N x 3 array, with z = 0
+    points_xyz = scan.points_array(z=True)
 
     rr.log_points("scan", points_xyz)
     rr.log_scalar("scan/rpm", scan.rpm)
@@ -12,16 +12,14 @@ from rclpy.duration import Duration
 from rclpy.time import Time
 from std_msgs.msg import Header, String, Float32
 from sensor_msgs_py.point_cloud2 import create_cloud_xyz32, PointCloud2
-
-def to_3D(points: List[Tuple[float,float]]) -> np.ndarray:
-    zz = np.zeros((len(points), 1))
-    return np.hstack([points, zz])
+from sensor_msgs.msg import LaserScan
 
 def timer_callback():
     global node
     global dev
     global pub
     global pubrpm
+    global pubscan
     node.get_logger().info("Scanning")
 
     if dev.alive():
@@ -32,8 +30,8 @@ def timer_callback():
           print("LiDAR disconnected?")
           return
 
-        # convert the 2D points to 3D
-        points_xyz = to_3D(scan.points)
+        # get the points as an N x 3 array, with z = 0
+        points_xyz = scan.points_array(z=True)
 
         hd = Header()
         hd.frame_id = "delta2"
@@ -42,6 +40,21 @@ def timer_callback():
         pclmsg = create_cloud_xyz32(hd, points_xyz)
         pub.publish(pclmsg)
 
+        # lidar angles go clockwise from +y, whereas LaserScan goes anti-clockwise from +x,
+        # so reverse the bins: reversed bin j (at 359-j degrees clockwise) is at j-269 degrees anti-clockwise.
+        ranges = scan.to_range_array(0.0, 359.0, 1.0, "nearest")
+        scanmsg = LaserScan()
+        scanmsg.header = hd
+        scanmsg.angle_min = math.radians(-269.0)
+        scanmsg.angle_max = math.radians(90.0)
+        scanmsg.angle_increment = math.radians(1.0)
+        scanmsg.scan_time = 60.0 / scan.rpm if scan.rpm > 0 else 0.0
+        scanmsg.range_min = 0.05
+        scanmsg.range_max = 8.0
+        scanmsg.ranges = ranges.ranges[::-1].tolist()
+        scanmsg.intensities = ranges.intensities[::-1].tolist()
+        pubscan.publish(scanmsg)
+
         rpmmsg = Float32()
         rpmmsg.data = float(scan.rpm)
         pubrpm.publish(rpmmsg)
@@ -60,6 +73,7 @@ def main(args=None):
     node.create_timer(0.01, timer_callback)
     pub = node.create_publisher(PointCloud2, "/cloud", 0)
     pubrpm = node.create_publisher(Float32, "/rpm", 0)
+    pubscan = node.create_publisher(LaserScan, "/scan", 0)
     rclpy.spin(node)
     rclpy.shutdown()
 
@@ -1,8 +1,48 @@
-from typing import Any, List, Tuple, Optional
+from typing import Any, List, Tuple, Optional, Union
+
+import numpy
+
+class LidarError(OSError): ...
+class SerialOpenError(LidarError): ...
+class DisconnectedError(LidarError): ...
+class HealthFaultError(LidarError): ...
+class ReadTimeoutError(LidarError): ...
+class WorkerPanickedError(LidarError): ...
+
+class ConnectionEvent:
+    connected: bool
+    @classmethod
+    def __init__(cls, *args, **kwargs) -> None: ...
+
+class DecoderStats:
+    byte_rate: float
+    bytes_discarded: int
+    bytes_received: int
+    crc_error_ratio: float
+    crc_failure_rate: float
+    crc_failures: int
+    discard_rate: float
+    frame_rate: float
+    frames_ok: int
+    header_resyncs: int
+    health_frames: int
+    measurement_frames: int
+    @classmethod
+    def __init__(cls, *args, **kwargs) -> None: ...
+    def as_json(self, *args, **kwargs) -> str: ...
+
+class DiscoveredPort:
+    frames: int
+    path: str
+    pid: Optional[int]
+    vid: Optional[int]
+    @classmethod
+    def __init__(cls, *args, **kwargs) -> None: ...
 
 class FullScan:
     complete: bool
     frames: List[MeasurementFrame]
+    missing_sectors: List[int]
     points: List[Tuple[float,float]]
     rpm: float
     timestamp: int
@@ -10,19 +50,74 @@ class FullScan:
     @classmethod
     def __init__(cls, *args, **kwargs) -> None: ...
     def as_json(self, *args, **kwargs) -> str: ...
+    def measurement_array(self) -> numpy.ndarray: ...
+    def points_array(self, z: bool = False) -> numpy.ndarray: ...
+    def to_csv(self) -> str: ...
+    def to_range_array(self, angle_min: float = 0.0, angle_max: float = 360.0, increment: float = 1.0, policy: str = "nearest") -> RangeArray: ...
 
-class Lidar:
+class GroupScan:
+    points: List[Tuple[float,float]]
+    scans: List[Tuple[str,FullScan]]
+    timestamp: int
+    timestamp_range: int
+    @classmethod
+    def __init__(cls, *args, **kwargs) -> None: ...
+    def as_json(self, *args, **kwargs) -> str: ...
+    def points_array(self, z: bool = False) -> numpy.ndarray: ...
+
+class HealthFrame:
+    error_codes: List[int]
+    motor_speed_low: bool
+    motor_stalled: bool
+    rpm: float
+    status: str
+    timestamp: int
     @classmethod
     def __init__(cls, *args, **kwargs) -> None: ...
+    def as_json(self, *args, **kwargs) -> str: ...
+    def to_wire_bytes(self) -> bytes: ...
+
+class Lidar:
+    dropped_frames: int
+    recording: bool
+    stats: DecoderStats
+    @classmethod
+    def __init__(cls, port: Optional[str] = None) -> None: ...
+    def __enter__(self) -> "Lidar": ...
+    def __exit__(self, *args) -> bool: ...
     def alive(self, *args, **kwargs) -> bool: ...
-    def open(self, *args, **kwargs) -> None: ...
-    def read_frame(self, *args, **kwargs) -> MeasurementFrame: ...
-    def read_full_scan(self, *args, **kwargs) -> FullScan: ...
+    def close(self) -> None: ...
+    def open(self, port: str, baud_rate: int = 115200, timeout: float = 0.02, parity: str = "none", flow_control: str = "none", dtr: Optional[bool] = None, rts: Optional[bool] = None, read_buffer_size: int = 256, thread_name: str = "lidar_decode_thread", thread_priority: Optional[int] = None) -> None: ...
+    def open_replay(self, path: str, speed: float = 1.0) -> None: ...
+    def read_event(self, timeout: Optional[float] = None) -> Union[MeasurementFrame, HealthFrame, ConnectionEvent]: ...
+    def read_frame(self, timeout: Optional[float] = None) -> MeasurementFrame: ...
+    def read_full_scan(self, timeout: Optional[float] = None) -> FullScan: ...
+    def set_pose(self, x: float = 0.0, y: float = 0.0, yaw: float = 0.0, mirrored: bool = False, angle_offset: float = 0.0) -> None: ...
+    def set_queue(self, capacity: Optional[int] = None, policy: str = "drop_oldest") -> None: ...
+    def set_reconnect_policy(self, enabled: bool = True, retry_interval: float = 0.5, max_backoff: float = 10.0, max_attempts: Optional[int] = None) -> None: ...
+    def start_recording(self, path: str) -> None: ...
+    def stop_recording(self) -> None: ...
+
+class LidarGroup:
+    ids: List[str]
+    @classmethod
+    def __init__(cls) -> None: ...
+    def __enter__(self) -> "LidarGroup": ...
+    def __exit__(self, *args) -> bool: ...
+    def add(self, id: str, lidar: Union[str, Lidar], x: float = 0.0, y: float = 0.0, yaw: float = 0.0, mirrored: bool = False, angle_offset: float = 0.0) -> None: ...
+    def alive(self) -> bool: ...
+    def close(self) -> None: ...
+    def read_event(self, timeout: Optional[float] = None) -> Tuple[str, Union[MeasurementFrame, HealthFrame, ConnectionEvent]]: ...
+    def read_scan(self, timeout: Optional[float] = None) -> GroupScan: ...
+    def set_max_skew(self, skew: float) -> None: ...
+    def set_merge_window(self, window: float) -> None: ...
+    def start(self) -> None: ...
 
 class Measurement:
     angle: float
     distance_mm: float
     point: Tuple[float,float]
+    robot_point: Tuple[float,float]
     signal_quality: int
     @classmethod
     def __init__(cls, *args, **kwargs) -> None: ...
@@ -33,9 +128,30 @@ class MeasurementFrame:
     offset_angle: float
     points: List[Tuple[float,float]]
     rpm: float
+    sector: int
     sector_angle: float
     start_angle: float
     timestamp: int
     @classmethod
     def __init__(cls, *args, **kwargs) -> None: ...
     def as_json(self, *args, **kwargs) -> str: ...
+    def measurement_array(self) -> numpy.ndarray: ...
+    def points_array(self, z: bool = False) -> numpy.ndarray: ...
+    def to_csv(self) -> str: ...
+    def to_wire_bytes(self) -> bytes: ...
+
+class RangeArray:
+    angle_increment: float
+    angle_max: float
+    angle_min: float
+    intensities: numpy.ndarray
+    ranges: numpy.ndarray
+    timestamp: int
+    @classmethod
+    def __init__(cls, *args, **kwargs) -> None: ...
+    def __len__(self) -> int: ...
+    def as_json(self, *args, **kwargs) -> str: ...
+
+def discover(timeout: float = 0.5, usb_ids: Optional[List[Tuple[int,int]]] = None, ports: Optional[List[str]] = None, baud_rate: int = 115200, min_frames: int = 3) -> List[DiscoveredPort]: ...
+def from_csv(path: str) -> List[MeasurementFrame]: ...
+def to_csv(path: str, data: Union[MeasurementFrame, FullScan, List[Union[MeasurementFrame, FullScan]]]) -> None: ...
@@ -35,4 +35,5 @@ setup(
         )
     ],
     include_package_data=True,
+    install_requires=["numpy"],
 )
\ No newline at end of file
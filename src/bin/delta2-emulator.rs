@@ -0,0 +1,72 @@
+// Streams emulated Delta-2 frames out of a pseudo-terminal, for testing without the hardware.
+// Prints the path to open (e.g. /dev/pts/3), then runs until killed, or for --duration seconds.
+//
+// delta2-emulator [--rpm 300] [--sectors 15] [--points 28] [--health 0.0] [--noise 0.0] [--drop 0.0] [--seed 1] [--duration SECONDS]
+//
+// --health, --noise and --drop are the chance of each frame being a health frame, followed by noise, or left out.
+use std::process::ExitCode;
+
+#[cfg(target_os = "linux")]
+fn run(args: &[String]) -> Result<(), String> {
+    use delta2_lidar_rs::emulator::{EmulatorConfig, PtyEmulator};
+    use std::io::Write;
+    use std::time::Duration;
+
+    fn parse<T: std::str::FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+        value.and_then(|v| v.parse().ok()).ok_or_else(|| format!("{} needs a number", flag))
+    }
+
+    let mut config = EmulatorConfig::new();
+    let mut duration: Option<Duration> = None;
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--rpm" => config = config.rpm(parse(flag, args.next())?),
+            "--sectors" => config = config.sectors(parse(flag, args.next())?),
+            "--points" => config = config.points_per_sector(parse(flag, args.next())?),
+            "--health" => config = config.health_probability(parse(flag, args.next())?),
+            "--noise" => config = config.noise_probability(parse(flag, args.next())?),
+            "--drop" => config = config.drop_probability(parse(flag, args.next())?),
+            "--seed" => config = config.seed(parse(flag, args.next())?),
+            "--duration" => {
+                // leave --duration out to run forever; inf, nan and negatives are rejected rather than panicking in sleep
+                let seconds: f64 = parse(flag, args.next())?;
+                let d = Duration::try_from_secs_f64(seconds).map_err(|e| format!("invalid --duration {}: {}", seconds, e))?;
+                duration = Some(d);
+            }
+            _ => return Err(format!("unknown argument '{}'", flag)),
+        }
+    }
+
+    let emulator = PtyEmulator::start(config).map_err(|e| e.to_string())?;
+    // flushed straight away, so a parent process reading our stdout gets the path before we finish
+    println!("{}", emulator.path());
+    let _ = std::io::stdout().flush();
+
+    match duration {
+        Some(d) => std::thread::sleep(d),
+        None => loop {
+            std::thread::park();
+        },
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run(_args: &[String]) -> Result<(), String> {
+    Err("the emulator needs linux pseudo-terminals".to_string())
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("delta2-emulator: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,175 @@
+// Capture files, which record the raw bytes received from the lidar along with when they arrived,
+// so that a replay reproduces the original timing (and frame timestamps).
+//
+// All integers are little-endian.
+//   header: 8 bytes, the magic "D2LIDAR" followed by a version byte (1)
+//   chunks: repeated until the end of the file, one per read from the serial port
+//     u64      receive timestamp, unix epoch nanoseconds (as PartialFrame::timestamp)
+//     u32      length N
+//     N bytes  raw data
+//
+// A file cut short part-way through a chunk (e.g. by a crash while recording) is read up to the last whole chunk.
+use log::warn;
+
+use std::io::{self, Read, Write};
+
+pub const CAPTURE_MAGIC: &[u8; 7] = b"D2LIDAR";
+pub const CAPTURE_VERSION: u8 = 1;
+
+// a single read from the serial port is nowhere near this big, so anything larger means a corrupt file
+const MAX_CHUNK_LENGTH: usize = 1 << 24;
+
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    // writes the file header to <inner>
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(CAPTURE_MAGIC)?;
+        inner.write_all(&[CAPTURE_VERSION])?;
+        Ok(CaptureWriter { inner })
+    }
+
+    pub fn write_chunk(&mut self, timestamp: u128, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(&(timestamp as u64).to_le_bytes())?;
+        self.inner.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.inner.write_all(data)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    // reads and checks the file header from <inner>
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0u8; 8];
+        inner.read_exact(&mut header)?;
+
+        if !is_capture_header(&header) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a lidar capture file"));
+        }
+        if header[7] != CAPTURE_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported capture version {}", header[7])));
+        }
+        Ok(CaptureReader { inner })
+    }
+
+    // returns the next (timestamp, data) chunk, or None at the end of the file
+    pub fn next_chunk(&mut self) -> io::Result<Option<(u128, Vec<u8>)>> {
+        let mut header = [0u8; 12];
+        match self.fill(&mut header)? {
+            0 => return Ok(None),
+            12 => {}
+            _ => return Ok(self.truncated()),
+        }
+
+        let timestamp = u64::from_le_bytes(header[..8].try_into().expect("8 bytes")) as u128;
+        let length = u32::from_le_bytes(header[8..].try_into().expect("4 bytes")) as usize;
+        if length > MAX_CHUNK_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("capture chunk of {} bytes is too long", length)));
+        }
+
+        let mut data = vec![0u8; length];
+        if self.fill(&mut data)? < length {
+            return Ok(self.truncated());
+        }
+        Ok(Some((timestamp, data)))
+    }
+
+    fn truncated(&self) -> Option<(u128, Vec<u8>)> {
+        warn!("Capture file ends part-way through a chunk, ignoring it");
+        None
+    }
+
+    // reads until <buf> is full or the file ends, returning how many bytes were read
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.inner.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+// checks whether the first 8 bytes of a file are a capture file header (of any version)
+pub fn is_capture_header(header: &[u8]) -> bool {
+    header.len() >= 8 && &header[..7] == CAPTURE_MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn capture(chunks: &[(u128, &[u8])]) -> Vec<u8> {
+        let mut data = vec![];
+        let mut writer = CaptureWriter::new(&mut data).unwrap();
+        for (timestamp, chunk) in chunks {
+            writer.write_chunk(*timestamp, chunk).unwrap();
+        }
+        writer.flush().unwrap();
+        data
+    }
+
+    fn read_all(data: &[u8]) -> io::Result<Vec<(u128, Vec<u8>)>> {
+        let mut reader = CaptureReader::new(Cursor::new(data))?;
+        let mut chunks = vec![];
+        while let Some(chunk) = reader.next_chunk()? {
+            chunks.push(chunk);
+        }
+        Ok(chunks)
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let data = capture(&[(1_692_000_000_123_456_789, &[0xAA, 0x00, 0x2C]), (1_692_000_000_223_456_789, &[]), (1_692_000_000_323_456_789, &[1, 2])]);
+        assert!(is_capture_header(&data));
+        assert_eq!(
+            read_all(&data).unwrap(),
+            vec![(1_692_000_000_123_456_789, vec![0xAA, 0x00, 0x2C]), (1_692_000_000_223_456_789, vec![]), (1_692_000_000_323_456_789, vec![1, 2])]
+        );
+    }
+
+    #[test]
+    fn truncated_chunks_are_ignored() {
+        let data = capture(&[(1, &[1, 2, 3]), (2, &[4, 5, 6])]);
+        // part-way through the last chunk's data, and part-way through its header
+        for length in [data.len() - 1, data.len() - 3 - 5] {
+            assert_eq!(read_all(&data[..length]).unwrap(), vec![(1, vec![1, 2, 3])]);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut data = capture(&[(1, &[1])]);
+        data[7] = CAPTURE_VERSION + 1;
+        assert_eq!(read_all(&data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut data = capture(&[(1, &[1])]);
+        data[0] = b'X';
+        assert!(!is_capture_header(&data));
+        assert_eq!(read_all(&data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // too short to even have a header
+        assert_eq!(read_all(b"D2LI").unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        // a chunk far too long to be real
+        let mut data = capture(&[]);
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(read_all(&data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,145 @@
+// Settings used by Lidar::open_with. The defaults suit the Delta-2, but some clones ship with
+// firmware running at a different baud rate, or need DTR/RTS driven to power up.
+//
+// let config = LidarConfig::new().baud_rate(230400).timeout(Duration::from_millis(50));
+// lidar.open_with("/dev/ttyUSB0".to_string(), config)?;
+use crate::error::{Error, Result};
+use crate::lidar::BAUD_RATE;
+
+pub use serialport::{FlowControl, Parity};
+
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct LidarConfig {
+    pub baud_rate: u32, // also used to pace replays of raw captures
+    pub timeout: Duration, // how long a read waits for data, which is also how quickly the worker notices close()
+    pub parity: Parity,
+    pub flow_control: FlowControl,
+    pub dtr: Option<bool>, // level to drive DTR to after opening the port, or None to leave it alone
+    pub rts: Option<bool>, // same for RTS
+    pub read_buffer_size: usize, // most bytes taken from the port per read
+    pub thread_name: String,
+    pub thread_priority: Option<i32>, // nice value of the worker thread, from -20 (highest) to 19 (lowest). linux only
+}
+
+impl Default for LidarConfig {
+    fn default() -> Self {
+        LidarConfig {
+            baud_rate: BAUD_RATE,
+            timeout: Duration::from_millis(20),
+            parity: Parity::None,
+            flow_control: FlowControl::None,
+            dtr: None,
+            rts: None,
+            read_buffer_size: 256,
+            thread_name: "lidar_decode_thread".to_string(),
+            thread_priority: None,
+        }
+    }
+}
+
+impl LidarConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    pub fn dtr(mut self, level: bool) -> Self {
+        self.dtr = Some(level);
+        self
+    }
+
+    pub fn rts(mut self, level: bool) -> Self {
+        self.rts = Some(level);
+        self
+    }
+
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = size;
+        self
+    }
+
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    pub fn thread_priority(mut self, nice: i32) -> Self {
+        self.thread_priority = Some(nice);
+        self
+    }
+
+    // catches settings which would stop the worker from working at all
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.baud_rate == 0 {
+            return Err(Error::InvalidParameter("baud rate must be above 0".to_string()));
+        }
+        if self.timeout.is_zero() {
+            return Err(Error::InvalidParameter("timeout must be above 0".to_string()));
+        }
+        if self.read_buffer_size == 0 {
+            return Err(Error::InvalidParameter("read buffer size must be above 0".to_string()));
+        }
+        if let Some(nice) = self.thread_priority {
+            if !(-20..=19).contains(&nice) {
+                return Err(Error::InvalidParameter(format!("thread priority {} is outside -20 to 19", nice)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(config: LidarConfig) -> String {
+        match config.validate() {
+            Err(Error::InvalidParameter(message)) => message,
+            other => panic!("expected InvalidParameter, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn accepts_sensible_configs() {
+        LidarConfig::default().validate().unwrap();
+        LidarConfig::new()
+            .baud_rate(115_200)
+            .timeout(Duration::from_millis(1))
+            .read_buffer_size(1)
+            .thread_priority(-20)
+            .validate()
+            .unwrap();
+        LidarConfig::new().thread_priority(19).validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        assert!(rejected(LidarConfig::new().baud_rate(0)).contains("baud rate"));
+        assert!(rejected(LidarConfig::new().timeout(Duration::ZERO)).contains("timeout"));
+        assert!(rejected(LidarConfig::new().read_buffer_size(0)).contains("buffer size"));
+        for nice in [-21, 20, i32::MIN, i32::MAX] {
+            assert!(rejected(LidarConfig::new().thread_priority(nice)).contains("priority"));
+        }
+    }
+}
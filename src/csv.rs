@@ -0,0 +1,209 @@
+// CSV files of measurements, one row each, for opening captures in a spreadsheet or pandas.
+//
+//   timestamp,frame,angle,distance_mm,quality,x,y
+//   1692000000123456789,0,0,1500,186,0,1.5
+//
+// timestamp is the frame's (unix epoch nanoseconds), and frame counts the frames written, so rows with the same
+// frame number came from the same frame. x and y are the point in metres, in the robot frame if the frame has a pose.
+// Frames without any measurements would have no rows, so are skipped, and not counted.
+//
+// Reading a file back gives the frames as they were written, other than the rpm (which isn't stored, so is 0)
+// and the pose (x and y are ignored, so the frames are in the lidar's frame again).
+use crate::protocol::{FullScan, Measurement, MeasurementFrame, SECTOR_ANGLE};
+
+use std::io::{self, BufRead, Write};
+
+pub const CSV_HEADER: &str = "timestamp,frame,angle,distance_mm,quality,x,y";
+
+pub struct CsvWriter<W: Write> {
+    inner: W,
+    frames: u64, // how many frames have been written
+}
+
+impl<W: Write> CsvWriter<W> {
+    // writes the header row to <inner>
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        writeln!(inner, "{}", CSV_HEADER)?;
+        Ok(CsvWriter { inner, frames: 0 })
+    }
+
+    pub fn write_frame(&mut self, frame: &MeasurementFrame) -> io::Result<()> {
+        if frame.measurements.is_empty() {
+            return Ok(());
+        }
+        for m in frame.measurements.iter() {
+            let (x, y) = m.point_in(&frame.pose);
+            writeln!(self.inner, "{},{},{},{},{},{},{}", frame.timestamp, self.frames, m.angle, m.distance_mm, m.signal_quality, x, y)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn write_scan(&mut self, scan: &FullScan) -> io::Result<()> {
+        for frame in scan.frames.iter() {
+            self.write_frame(frame)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+pub struct CsvReader<R: BufRead> {
+    inner: R,
+    line: usize, // number of the last line read, for errors
+    next: Option<(u128, u64, Measurement)>, // the first row of the next frame, once it has been read
+}
+
+impl<R: BufRead> CsvReader<R> {
+    // reads and checks the header row from <inner>
+    pub fn new(inner: R) -> io::Result<Self> {
+        let mut reader = CsvReader { inner, line: 0, next: None };
+        match reader.read_line()? {
+            // Excel starts UTF-8 CSV files with a byte order mark
+            Some(header) if header.strip_prefix('\u{feff}').unwrap_or(&header) == CSV_HEADER => Ok(reader),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("not a measurement CSV file, expected the header '{}'", CSV_HEADER))),
+        }
+    }
+
+    // returns the next frame, or None at the end of the file
+    pub fn next_frame(&mut self) -> io::Result<Option<MeasurementFrame>> {
+        let (timestamp, index, first) = match self.next.take() {
+            Some(row) => row,
+            None => match self.read_row()? {
+                Some(row) => row,
+                None => return Ok(None),
+            },
+        };
+
+        let mut measurements = vec![first];
+        while let Some(row) = self.read_row()? {
+            if row.1 != index {
+                self.next = Some(row);
+                break;
+            }
+            measurements.push(row.2);
+        }
+
+        Ok(Some(MeasurementFrame {
+            rpm: 0.0,
+            // as the decoder works it out, see RawFrame::angle_step
+            offset_angle: SECTOR_ANGLE / (measurements.len() as f32),
+            start_angle: measurements[0].angle,
+            timestamp,
+            measurements,
+            ..MeasurementFrame::default()
+        }))
+    }
+
+    // the next non-empty line, without its line ending
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.inner.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            // spreadsheets tend to save with \r\n
+            let trimmed = line.trim();
+            if !trimmed.is_empty() {
+                return Ok(Some(trimmed.to_string()));
+            }
+        }
+    }
+
+    // the next (timestamp, frame, measurement) row
+    fn read_row(&mut self) -> io::Result<Option<(u128, u64, Measurement)>> {
+        let line = match self.read_line()? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let fields = line.split(',').map(|f| f.trim()).collect::<Vec<_>>();
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("line {}: expected {}", self.line, CSV_HEADER));
+        if fields.len() != 7 {
+            return Err(invalid());
+        }
+
+        let timestamp = fields[0].parse().map_err(|_| invalid())?;
+        let index = fields[1].parse().map_err(|_| invalid())?;
+        let measurement = Measurement {
+            angle: fields[2].parse().map_err(|_| invalid())?,
+            distance_mm: fields[3].parse().map_err(|_| invalid())?,
+            signal_quality: fields[4].parse().map_err(|_| invalid())?,
+        };
+        Ok(Some((timestamp, index, measurement)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn frame(timestamp: u128, count: usize) -> MeasurementFrame {
+        let measurements = (0..count)
+            .map(|i| Measurement { angle: 24.0 + (i as f32) * 2.0, signal_quality: 100 + i as u8, distance_mm: 1000.0 + (i as f32) * 0.25 })
+            .collect();
+        MeasurementFrame { start_angle: 24.0, timestamp, measurements, ..MeasurementFrame::default() }
+    }
+
+    fn read_all(data: &[u8]) -> io::Result<Vec<MeasurementFrame>> {
+        let mut reader = CsvReader::new(Cursor::new(data))?;
+        let mut frames = vec![];
+        while let Some(frame) = reader.next_frame()? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let written = [frame(100, 12), frame(200, 0), frame(300, 3)];
+        let mut writer = CsvWriter::new(vec![]).unwrap();
+        for frame in written.iter() {
+            writer.write_frame(frame).unwrap();
+        }
+        let data = writer.into_inner();
+
+        // the empty frame has no rows, and doesn't use up a frame number
+        let text = String::from_utf8(data.clone()).unwrap();
+        assert_eq!(text.lines().count(), 1 + 12 + 3);
+        assert!(text.lines().last().unwrap().starts_with("300,1,"));
+
+        let read = read_all(&data).unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, written) in read.iter().zip([&written[0], &written[2]]) {
+            assert_eq!(read.timestamp, written.timestamp);
+            assert_eq!(read.start_angle, written.start_angle);
+            assert_eq!(read.measurements.len(), written.measurements.len());
+            for (r, w) in read.measurements.iter().zip(written.measurements.iter()) {
+                assert_eq!((r.angle, r.distance_mm, r.signal_quality), (w.angle, w.distance_mm, w.signal_quality));
+            }
+        }
+    }
+
+    #[test]
+    fn reads_files_starting_with_a_byte_order_mark() {
+        let data = format!("\u{feff}{}\r\n5,0,10,1500,186,0.26,1.48\r\n", CSV_HEADER);
+        let frames = read_all(data.as_bytes()).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].timestamp, 5);
+        assert_eq!(frames[0].measurements[0].distance_mm, 1500.0);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(read_all(b"a,b,c\n1,2,3\n").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(read_all(b"").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let data = format!("{}\n5,0,10,far,186,0,0\n", CSV_HEADER);
+        assert_eq!(read_all(data.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
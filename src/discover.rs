@@ -0,0 +1,143 @@
+// Finds the serial ports which have a Delta-2 attached, by listening on each one for valid frames,
+// rather than relying on the device path (which changes as other USB-serial adapters come and go).
+use crate::config::LidarConfig;
+use crate::error::Result;
+use crate::protocol::FrameDecoder;
+use crate::transport::Endpoint;
+
+use log::debug;
+use serde::Serialize;
+use serialport::SerialPortType;
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// USB (vendor id, product id)
+pub type UsbId = (u16, u16);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredPort {
+    pub path: String,
+    pub vid: Option<u16>, // USB vendor/product ids, if the port is a USB device
+    pub pid: Option<u16>,
+    pub frames: u64, // valid frames received while listening
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    pub listen: Duration, // how long to listen on each port (all ports are listened to at the same time)
+    pub min_frames: u64, // frames needed before a port counts as a lidar
+    pub usb_ids: Vec<UsbId>, // only listen on USB ports with one of these (vid, pid)s, unless empty
+    pub ports: Option<Vec<String>>, // listen on these ports, instead of every port on the system
+    pub config: LidarConfig, // port settings, e.g. the baud rate
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions {
+            // the lidar sends ~75 frames per second once the motor is up to speed
+            listen: Duration::from_millis(500),
+            min_frames: 3,
+            usb_ids: vec![],
+            ports: None,
+            config: LidarConfig::default(),
+        }
+    }
+}
+
+// returns the ports which sent at least min_frames valid frames (measurement or health)
+pub fn discover(options: &DiscoveryOptions) -> Result<Vec<DiscoveredPort>> {
+    let candidates = match &options.ports {
+        Some(ports) => ports.iter().map(|p| (p.clone(), usb_ids(p))).collect(),
+        None => serial_ports()?,
+    };
+
+    let listeners: Vec<_> = candidates
+        .into_iter()
+        .filter(|(_, ids)| options.usb_ids.is_empty() || ids.map(|i| options.usb_ids.contains(&i)).unwrap_or(false))
+        .map(|(path, ids)| {
+            let options = options.clone();
+            thread::spawn(move || {
+                let frames = match listen(&path, &options) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        debug!("Not a lidar on {}: {}", path, e);
+                        0
+                    }
+                };
+                DiscoveredPort { path, vid: ids.map(|i| i.0), pid: ids.map(|i| i.1), frames }
+            })
+        })
+        .collect();
+
+    Ok(listeners
+        .into_iter()
+        .filter_map(|l| l.join().ok())
+        .filter(|p| p.frames >= options.min_frames)
+        .collect())
+}
+
+// counts the valid frames received on <path>, stopping early once there are enough
+fn listen(path: &str, options: &DiscoveryOptions) -> Result<u64> {
+    let endpoint = Endpoint::Serial { path: path.to_string(), baud: None };
+    let mut port = endpoint.open(&options.config)?;
+    let mut decoder = FrameDecoder::new();
+    let mut buf = vec![0u8; options.config.read_buffer_size];
+    let mut frames = 0;
+
+    // a listen time too long to represent never ends, other than by finding enough frames
+    let deadline = Instant::now().checked_add(options.listen);
+    while deadline.map(|d| Instant::now() < d).unwrap_or(true) && frames < options.min_frames {
+        match port.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => frames += decoder.push(&buf[..n]).count() as u64,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(frames)
+}
+
+// every serial port on the system, with its USB (vid, pid) if known
+fn serial_ports() -> Result<Vec<(String, Option<UsbId>)>> {
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .map(|p| {
+            let path = device_path(&p.port_name);
+            let ids = match p.port_type {
+                SerialPortType::UsbPort(info) => Some((info.vid, info.pid)),
+                _ => usb_ids(&path),
+            };
+            (path, ids)
+        })
+        .collect())
+}
+
+// without libudev, serialport lists linux ports by their sysfs path
+fn device_path(name: &str) -> String {
+    match name.strip_prefix("/sys/class/tty/") {
+        Some(tty) => format!("/dev/{}", tty),
+        None => name.to_string(),
+    }
+}
+
+// looks up the USB ids of a linux tty device in sysfs: the device link points at the USB interface,
+// whose parent is the USB device itself
+#[cfg(target_os = "linux")]
+fn usb_ids(path: &str) -> Option<UsbId> {
+    let tty = path.rsplit('/').next()?;
+    let interface = std::fs::canonicalize(format!("/sys/class/tty/{}/device", tty)).ok()?;
+    let usb_device = interface.parent()?;
+
+    let read_id = |name: &str| -> Option<u16> {
+        let id = std::fs::read_to_string(usb_device.join(name)).ok()?;
+        u16::from_str_radix(id.trim(), 16).ok()
+    };
+    Some((read_id("idVendor")?, read_id("idProduct")?))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn usb_ids(_path: &str) -> Option<UsbId> {
+    None
+}
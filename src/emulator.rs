@@ -0,0 +1,328 @@
+// A virtual Delta-2, for testing without the hardware. FrameGenerator makes the bytes the lidar would send,
+// and (on linux) PtyEmulator streams them out of a pseudo-terminal, which can be opened like a serial port.
+//
+// let emulator = PtyEmulator::start(EmulatorConfig::new().rpm(360.0).drop_probability(0.01))?;
+// lidar.open(emulator.path().to_string())?;
+//
+// The delta2-emulator binary does the same from the command line, for other processes (e.g. python tests) to open.
+use crate::error::{Error, Result};
+use crate::frame::{RawFrame, HEADER, MAX_MEASUREMENTS};
+use crate::protocol::Measurement;
+
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct EmulatorConfig {
+    pub rpm: f32, // motor speed, 0 to 765 (the protocol sends it in steps of 3)
+    pub sectors: usize, // frames per revolution. The decoder assumes 15, of 24 degrees each
+    pub points_per_sector: usize, // measurements in each frame
+    pub health_probability: f64, // chance of a health frame (motor speed low) being sent in place of each measurement frame
+    pub noise_probability: f64, // chance of a burst of random bytes after each frame
+    pub drop_probability: f64, // chance of each frame being left out
+    pub seed: u64, // for the random choices above, so that runs are repeatable
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig {
+            rpm: 300.0,
+            sectors: 15,
+            points_per_sector: 28,
+            health_probability: 0.0,
+            noise_probability: 0.0,
+            drop_probability: 0.0,
+            seed: 1,
+        }
+    }
+}
+
+impl EmulatorConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rpm(mut self, rpm: f32) -> Self {
+        self.rpm = rpm;
+        self
+    }
+
+    pub fn sectors(mut self, sectors: usize) -> Self {
+        self.sectors = sectors;
+        self
+    }
+
+    pub fn points_per_sector(mut self, points: usize) -> Self {
+        self.points_per_sector = points;
+        self
+    }
+
+    pub fn health_probability(mut self, p: f64) -> Self {
+        self.health_probability = p;
+        self
+    }
+
+    pub fn noise_probability(mut self, p: f64) -> Self {
+        self.noise_probability = p;
+        self
+    }
+
+    pub fn drop_probability(mut self, p: f64) -> Self {
+        self.drop_probability = p;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if !(self.rpm > 0.0 && self.rpm <= 765.0) {
+            return Err(Error::InvalidParameter(format!("rpm {} is outside 0 to 765", self.rpm)));
+        }
+        if self.sectors == 0 {
+            return Err(Error::InvalidParameter("sectors must be above 0".to_string()));
+        }
+        if !(1..=MAX_MEASUREMENTS).contains(&self.points_per_sector) {
+            return Err(Error::InvalidParameter(format!("points per sector {} is outside 1 to {}", self.points_per_sector, MAX_MEASUREMENTS)));
+        }
+        for (name, p) in [("health", self.health_probability), ("noise", self.noise_probability), ("drop", self.drop_probability)] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(Error::InvalidParameter(format!("{} probability {} is outside 0 to 1", name, p)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// small deterministic PRNG, so that runs are repeatable (also used by the simulator and the tests).
+// the seed must not be 0, which it would never leave
+pub struct XorShift(pub u64);
+
+impl XorShift {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % (n as u64)) as usize
+    }
+
+    // uniformly distributed from 0 to 1
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() as f64) / (u64::MAX as f64)
+    }
+
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.uniform() < p
+    }
+}
+
+type RangeFn = Box<dyn FnMut(f32) -> Measurement + Send>;
+
+// Makes the byte stream of a spinning lidar, one sector at a time
+pub struct FrameGenerator {
+    config: EmulatorConfig,
+    ranges: RangeFn,
+    sector: usize,
+    rng: XorShift,
+}
+
+impl FrameGenerator {
+    pub fn new(config: EmulatorConfig) -> Result<Self> {
+        config.validate()?;
+        let rng = XorShift(config.seed.max(1));
+        Ok(FrameGenerator { config, ranges: Box::new(room), sector: 0, rng })
+    }
+
+    // what the lidar sees at each angle (in degrees, as in Measurement). By default, the walls of a 4 x 3 metre room
+    pub fn ranges(mut self, ranges: impl FnMut(f32) -> Measurement + Send + 'static) -> Self {
+        self.ranges = Box::new(ranges);
+        self
+    }
+
+    pub fn config(&self) -> &EmulatorConfig {
+        &self.config
+    }
+
+    // how long the lidar takes to sweep one sector
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_secs_f64(60.0 / (self.config.rpm as f64) / (self.config.sectors as f64))
+    }
+
+    // appends the bytes sent while sweeping the next sector, which can be nothing if the frame was dropped
+    pub fn next_chunk(&mut self, out: &mut Vec<u8>) {
+        let sector_angle = 360.0 / (self.config.sectors as f32);
+        let start_angle = (self.sector as f32) * sector_angle;
+        self.sector = (self.sector + 1) % self.config.sectors;
+
+        if !self.rng.chance(self.config.drop_probability) {
+            let frame = if self.rng.chance(self.config.health_probability) {
+                RawFrame::encode_health(self.config.rpm, &[], 0)
+            } else {
+                let step = sector_angle / (self.config.points_per_sector as f32);
+                let measurements = (0..self.config.points_per_sector)
+                    .map(|i| (self.ranges)(start_angle + (i as f32) * step))
+                    .collect::<Vec<_>>();
+                RawFrame::encode_measurements(self.config.rpm, start_angle, &measurements, 0)
+            };
+            out.extend_from_slice(frame.expect("config was validated").as_bytes());
+        }
+
+        if self.rng.chance(self.config.noise_probability) {
+            // which can contain fake headers, as line noise sometimes does
+            for _ in 0..1 + self.rng.below(16) {
+                let byte = if self.rng.chance(0.1) { HEADER } else { self.rng.next_u64() as u8 };
+                out.push(byte);
+            }
+        }
+    }
+}
+
+// distance to the walls of a 4 x 3 metre room, from the middle of it
+fn room(angle: f32) -> Measurement {
+    // 0 degrees is along +y, going clockwise towards +x (see Measurement::point)
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let distance = f32::min(2.0 / sin.abs(), 1.5 / cos.abs()) * 1000.0;
+    Measurement { angle, signal_quality: 200, distance_mm: distance }
+}
+
+#[cfg(target_os = "linux")]
+pub use self::pty::PtyEmulator;
+
+#[cfg(target_os = "linux")]
+mod pty {
+    use super::{EmulatorConfig, FrameGenerator};
+    use crate::error::Result;
+
+    use log::warn;
+
+    use std::ffi::CStr;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    // Streams a FrameGenerator out of a pseudo-terminal, in real time, until stopped or dropped.
+    pub struct PtyEmulator {
+        path: String,
+        stop: Arc<AtomicBool>,
+        worker: Option<JoinHandle<()>>,
+    }
+
+    impl PtyEmulator {
+        pub fn start(config: EmulatorConfig) -> Result<Self> {
+            Self::spawn(FrameGenerator::new(config)?)
+        }
+
+        pub fn spawn(mut generator: FrameGenerator) -> Result<Self> {
+            let (mut master, slave, path) = open_pty()?;
+            let stop = Arc::new(AtomicBool::new(false));
+            let worker_stop = stop.clone();
+
+            let worker = thread::Builder::new().name("lidar_emulator_thread".to_string()).spawn(move || {
+                let interval = generator.frame_interval();
+                let mut chunk = vec![];
+                let mut due = Instant::now();
+
+                while !worker_stop.load(Ordering::Relaxed) {
+                    chunk.clear();
+                    generator.next_chunk(&mut chunk);
+                    if let Err(e) = write_chunk(&mut master, &slave, &chunk, &worker_stop) {
+                        warn!("Emulator stopped, failed to write to the pty: {}", e);
+                        break;
+                    }
+
+                    due += interval;
+                    let now = Instant::now();
+                    if due > now {
+                        thread::sleep(due - now);
+                    } else if now - due > Duration::from_secs(1) {
+                        // fallen well behind (e.g. the machine was suspended), so don't send a burst to catch up
+                        due = now;
+                    }
+                }
+            })?;
+
+            Ok(PtyEmulator { path, stop, worker: Some(worker) })
+        }
+
+        // the pty to open, e.g. /dev/pts/3
+        pub fn path(&self) -> &str {
+            &self.path
+        }
+
+        pub fn stop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(worker) = self.worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+
+    impl Drop for PtyEmulator {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    // opens a pty, returning the master, the slave, and the path to the slave.
+    // The slave is kept open, so that writes don't fail while nothing else has it open.
+    fn open_pty() -> io::Result<(File, File, String)> {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut name = [0 as libc::c_char; 64];
+            let result = libc::ptsname_r(fd, name.as_mut_ptr(), name.len());
+            if result != 0 {
+                return Err(io::Error::from_raw_os_error(result));
+            }
+            let path = CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned();
+
+            let slave = OpenOptions::new().read(true).write(true).custom_flags(libc::O_NOCTTY).open(&path)?;
+            // raw mode, so the line discipline doesn't mangle the frames (e.g. turning 0x0A into 0x0D 0x0A)
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((master, slave, path))
+        }
+    }
+
+    // writes all of <chunk>, unless <stop> is set while waiting for room
+    fn write_chunk(master: &mut File, slave: &File, mut chunk: &[u8], stop: &AtomicBool) -> io::Result<()> {
+        while !chunk.is_empty() && !stop.load(Ordering::Relaxed) {
+            match master.write(chunk) {
+                Ok(n) => chunk = &chunk[n..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // nobody is reading, so throw away what's waiting, like a UART overrunning
+                    if unsafe { libc::tcflush(slave.as_raw_fd(), libc::TCIFLUSH) } != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    // the flush can take a moment to make room
+                    thread::sleep(Duration::from_millis(5));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+// Crate-specific error type, so that callers can react to each failure cause
+// (e.g. a stalled motor vs. an unplugged cable) without matching on error messages.
+use crate::protocol::HealthFrame;
+
+use std::fmt::Display;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    SerialOpen(serialport::Error), // the serial port could not be opened
+    Io(io::Error), // any other read/write failure on the underlying port
+    NotOpened, // Lidar::open has not been called (successfully) yet
+    Disconnected, // the worker thread has stopped, so no more frames will arrive
+    FrameFull, // tried to write more bytes into a PartialFrame than it wanted
+    HealthFault(HealthFrame), // the lidar sent a health frame instead of a measurement
+    Timeout, // no data arrived within the requested time
+    WorkerPanicked, // the worker thread panicked
+    InvalidParameter(String), // an argument was out of range, e.g. a negative angle increment
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::SerialOpen(e) => f.write_str(&format!("failed to open serial port: {}", e)),
+            Error::Io(e) => f.write_str(&format!("i/o error: {}", e)),
+            Error::NotOpened => f.write_str("lidar has not been opened"),
+            Error::Disconnected => f.write_str("lidar disconnected"),
+            Error::FrameFull => f.write_str("frame is full"),
+            Error::HealthFault(h) => f.write_str(&format!("lidar health fault: {}", h)),
+            Error::Timeout => f.write_str("timed out waiting for lidar data"),
+            Error::WorkerPanicked => f.write_str("lidar worker thread panicked"),
+            Error::InvalidParameter(msg) => f.write_str(&format!("invalid parameter: {}", msg)),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::SerialOpen(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serialport::Error> for Error {
+    fn from(value: serialport::Error) -> Self {
+        Error::SerialOpen(value)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        // io::Errors produced by this crate (e.g. PartialFrame's Write impl) carry an Error inside them,
+        // so unwrap those back into the original variant.
+        if value.get_ref().map(|e| e.is::<Error>()).unwrap_or(false) {
+            let inner = value.into_inner().expect("io::Error has an inner error");
+            *inner.downcast::<Error>().expect("inner error is a crate Error")
+        } else {
+            Error::Io(value)
+        }
+    }
+}
@@ -0,0 +1,635 @@
+// The heap-free core of the protocol decoder, which builds without the 'std' feature so that it can
+// run on a microcontroller (e.g. a co-processor forwarding scans over another link).
+// The std FrameDecoder in protocol.rs is built on top of the FrameParser here, so both decode the same way.
+//
+// let mut parser = FrameParser::new();
+// let mut rest = &bytes[..];
+// while !rest.is_empty() {
+//     rest = &rest[parser.push(rest, timestamp)..];
+//     while let Some(frame) = parser.next_frame() { ... }
+// }
+//
+// Timestamps are whatever the caller wants them to be (e.g. a microsecond tick counter), as long as they
+// count nanoseconds if the DecoderStats rates are to be per-second.
+use core::fmt::{self, Display};
+
+use log::debug;
+use serde::Serialize;
+
+pub const HEADER: u8 = 0xAA;
+pub const COMMAND_MEASUREMENT: u8 = 0xAD;
+pub const COMMAND_HEALTH: u8 = 0xAE;
+pub const HEADER_LENGTH: usize = 8;
+
+// The Delta-2 sends around 28 measurements per frame. Anything claiming to be a lot longer than that is
+// taken to be a corrupted header, so that it doesn't hold up decoding while it waits for bytes.
+pub const MAX_MEASUREMENTS: usize = 128;
+pub const MAX_PAYLOAD_LENGTH: usize = 5 + 3 * MAX_MEASUREMENTS;
+pub const MAX_FRAME_LENGTH: usize = HEADER_LENGTH + MAX_PAYLOAD_LENGTH + 2;
+
+// room for a whole frame, plus a frame's worth of bytes which might hide a real header
+const BUFFER_LENGTH: usize = 2 * MAX_FRAME_LENGTH;
+// headers which can be assembled at the same time. real headers are rare in noise, and fake ones fail quickly.
+// Any more wait to be found until earlier ones have failed.
+const MAX_CANDIDATES: usize = 32;
+// pushes whose timestamps are remembered while their bytes wait to be searched for headers
+const MAX_CHUNKS: usize = 8;
+
+// 180 degrees means the 0-point is opposite the motor location, rather than on-top of the motor.
+// Any other mounting can be corrected for with SensorPose::angle_offset.
+pub const ZERO_ANGLE_OFFSET: f32 = 180.0;
+
+// the Delta-2 'CRC' is actually a plain 16-bit sum of every byte before it
+pub fn checksum(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |sum, x| sum.wrapping_add(*x as u16))
+}
+
+// checks a byte against the fixed parts of the frame header, given its position in the frame.
+// bytes past the header (payload + crc) are always accepted.
+pub fn header_byte_valid(index: usize, byte: u8) -> bool {
+    match (index, byte) {
+        (0, HEADER) => true,
+        (1 | 2, _) => true, // frame length
+        (3, 0x01) => true, // type
+        (4, 0x61) => true, // protocol
+        (5, COMMAND_MEASUREMENT | COMMAND_HEALTH) => true,
+        (6 | 7, _) => true, // payload length
+        (i, _) => i >= HEADER_LENGTH,
+    }
+}
+
+#[derive(Serialize)]
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub angle : f32, // degrees
+    pub signal_quality: u8,
+    pub distance_mm : f32,
+}
+
+impl Default for Measurement {
+    fn default() -> Self {
+        Measurement {
+            angle: 0.0,
+            signal_quality: 0,
+            distance_mm: 0.0,
+        }
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:3.1} deg - {:3.1} cm", self.angle, self.distance_mm / 10.0)
+    }
+}
+
+// A complete frame with a valid checksum, in a fixed size buffer
+#[derive(Clone)]
+pub struct RawFrame {
+    data: [u8; MAX_FRAME_LENGTH],
+    length: usize,
+    pub timestamp: u128, // as given to FrameParser::push with the header byte
+}
+
+impl RawFrame {
+    // checks that <data> holds exactly one whole frame, with a known command and a valid checksum
+    pub fn from_bytes(data: &[u8], timestamp: u128) -> Option<RawFrame> {
+        if data.len() < HEADER_LENGTH || !data[..HEADER_LENGTH].iter().enumerate().all(|(i, b)| header_byte_valid(i, *b)) {
+            return None;
+        }
+        let length = HEADER_LENGTH + u16::from_be_bytes([data[6], data[7]]) as usize + 2;
+        if length != data.len() || length > MAX_FRAME_LENGTH {
+            return None;
+        }
+        if checksum(&data[..length - 2]) != u16::from_be_bytes([data[length - 2], data[length - 1]]) {
+            return None;
+        }
+
+        let mut frame = RawFrame { data: [0; MAX_FRAME_LENGTH], length, timestamp };
+        frame.data[..length].copy_from_slice(data);
+        Some(frame)
+    }
+
+    // builds a frame around <payload>, filling in the header, lengths and checksum.
+    // None if the payload is longer than MAX_PAYLOAD_LENGTH.
+    pub fn encode(command: u8, payload: &[u8], timestamp: u128) -> Option<RawFrame> {
+        if payload.len() > MAX_PAYLOAD_LENGTH {
+            return None;
+        }
+        let length = HEADER_LENGTH + payload.len() + 2;
+        let mut data = [0u8; MAX_FRAME_LENGTH];
+
+        data[0] = HEADER;
+        // the frame length doesn't include the checksum
+        data[1..3].copy_from_slice(&((length - 2) as u16).to_be_bytes());
+        data[3] = 0x01;
+        data[4] = 0x61;
+        data[5] = command;
+        data[6..8].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+        data[HEADER_LENGTH..length - 2].copy_from_slice(payload);
+        let crc = checksum(&data[..length - 2]);
+        data[length - 2..length].copy_from_slice(&crc.to_be_bytes());
+
+        Some(RawFrame { data, length, timestamp })
+    }
+
+    // the reverse of measurements(), for emulating a lidar or making test data.
+    // Only the quality and distance of each measurement is sent, their angles follow on from <start_angle>.
+    // Values are rounded to what the protocol can hold. None if there are more than MAX_MEASUREMENTS.
+    pub fn encode_measurements(rpm: f32, start_angle: f32, measurements: &[Measurement], timestamp: u128) -> Option<RawFrame> {
+        if measurements.len() > MAX_MEASUREMENTS {
+            return None;
+        }
+        let mut payload = [0u8; MAX_PAYLOAD_LENGTH];
+
+        payload[0] = encode_rpm(rpm);
+        // the 'offset angle' field is ignored by the decoder, see angle_step
+        let mut angle = (start_angle - ZERO_ANGLE_OFFSET) % 360.0;
+        if angle < 0.0 {
+            angle += 360.0;
+        }
+        let raw_angle = ((angle * 100.0 + 0.5) as u16) % 36000;
+        payload[3..5].copy_from_slice(&raw_angle.to_be_bytes());
+
+        for (i, m) in measurements.iter().enumerate() {
+            let distance = (m.distance_mm * 4.0 + 0.5) as u16;
+            payload[5 + 3 * i] = m.signal_quality;
+            payload[6 + 3 * i..8 + 3 * i].copy_from_slice(&distance.to_be_bytes());
+        }
+        RawFrame::encode(COMMAND_MEASUREMENT, &payload[..5 + 3 * measurements.len()], timestamp)
+    }
+
+    // a health frame, sent by the lidar when it can't measure. None if there are too many error codes.
+    pub fn encode_health(rpm: f32, error_codes: &[u8], timestamp: u128) -> Option<RawFrame> {
+        if 1 + error_codes.len() > MAX_PAYLOAD_LENGTH {
+            return None;
+        }
+        let mut payload = [0u8; MAX_PAYLOAD_LENGTH];
+        payload[0] = encode_rpm(rpm);
+        payload[1..1 + error_codes.len()].copy_from_slice(error_codes);
+        RawFrame::encode(COMMAND_HEALTH, &payload[..1 + error_codes.len()], timestamp)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.length]
+    }
+
+    pub fn command(&self) -> u8 {
+        self.data[5]
+    }
+
+    pub fn is_measurement_type(&self) -> bool {
+        self.command() == COMMAND_MEASUREMENT
+    }
+
+    pub fn is_health_type(&self) -> bool {
+        self.command() == COMMAND_HEALTH
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[HEADER_LENGTH..self.length - 2]
+    }
+
+    // both frame types start with the motor speed, in 0.05 rps units
+    pub fn rpm(&self) -> f32 {
+        self.payload().first().map(|r| (*r as f32) * 3.0).unwrap_or(0.0)
+    }
+
+    pub fn measurements_count(&self) -> usize {
+        match self.is_measurement_type() {
+            true => self.payload().len().saturating_sub(5) / 3,
+            false => 0,
+        }
+    }
+
+    // angle of the first measurement, in degrees
+    pub fn start_angle(&self) -> f32 {
+        let payload = self.payload();
+        if !self.is_measurement_type() || payload.len() < 5 {
+            return 0.0;
+        }
+        let raw = u16::from_be_bytes([payload[3], payload[4]]);
+        ((raw as f32) * 0.01 + ZERO_ANGLE_OFFSET) % 360.0
+    }
+
+    // degrees between each measurement.
+    // The 'offset angle' field doesn't seem to be this (or anything useful), but every frame covers 24 degrees.
+    pub fn angle_step(&self) -> f32 {
+        match self.measurements_count() {
+            0 => 0.0,
+            n => 24.0 / (n as f32),
+        }
+    }
+
+    pub fn measurements(&self) -> impl Iterator<Item = Measurement> + '_ {
+        let start_angle = self.start_angle();
+        let step = self.angle_step();
+        self.payload()
+            .get(5..)
+            .unwrap_or(&[])
+            .chunks_exact(3)
+            .take(self.measurements_count())
+            .enumerate()
+            .map(move |(i, m)| Measurement {
+                angle: start_angle + (i as f32) * step,
+                signal_quality: m[0],
+                distance_mm: (u16::from_be_bytes([m[1], m[2]]) as f32) * 0.25,
+            })
+    }
+}
+
+// motor speed in the 0.05 rps units of the first payload byte (casting rounds down, and saturates at 0 and 255)
+fn encode_rpm(rpm: f32) -> u8 {
+    (rpm / 3.0 + 0.5) as u8
+}
+
+impl fmt::Debug for RawFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawFrame").field("data", &self.as_bytes()).field("timestamp", &self.timestamp).finish()
+    }
+}
+
+// Counters maintained by the FrameParser, useful for spotting a degrading link (e.g. a bad cable).
+// The rates are per-second, averaged over windows of (at least) one second of pushed data.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DecoderStats {
+    pub bytes_received: u64,
+    pub frames_ok: u64, // frames with a valid CRC, of any type
+    pub measurement_frames: u64,
+    pub health_frames: u64,
+    pub crc_failures: u64, // complete frames which failed their CRC check
+    pub header_resyncs: u64, // times the decoder lost the stream, and found it again
+    pub bytes_discarded: u64, // bytes which weren't part of any good frame
+    pub byte_rate: f32,
+    pub frame_rate: f32,
+    pub crc_failure_rate: f32,
+    pub discard_rate: f32,
+}
+
+impl DecoderStats {
+    // fraction of complete frames which failed their CRC check
+    pub fn crc_error_ratio(&self) -> f32 {
+        let total = self.frames_ok + self.crc_failures;
+        if total == 0 {
+            return 0.0;
+        }
+        (self.crc_failures as f32) / (total as f32)
+    }
+}
+
+impl Display for DecoderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames ({:.1}/s), {} crc failures ({:.2}%), {} resyncs, {} bytes discarded",
+            self.frames_ok,
+            self.frame_rate,
+            self.crc_failures,
+            100.0 * self.crc_error_ratio(),
+            self.header_resyncs,
+            self.bytes_discarded
+        )
+    }
+}
+
+// What we know about a candidate frame, given the bytes buffered so far.
+enum Candidate {
+    Pending, // looks OK, needs more bytes
+    Complete(usize), // all bytes received, and the CRC is valid. Holds the frame length.
+    BadHeader, // one of the fixed header bytes was wrong, or the frame would be too long
+    CrcMismatch, // all bytes received, but the CRC is wrong
+}
+
+// Whether the parser is following the stream of frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sync {
+    Starting, // no good frame yet, so discarding bytes is just joining the stream part-way through a frame
+    Synced, // the last bytes decoded were a good frame
+    Lost, // bytes have been discarded since the last good frame
+}
+
+// Streaming decoder, which turns an arbitrary stream of bytes into complete, CRC-checked frames.
+//
+// Every 0xAA byte is treated as a 'candidate' frame header, and all candidates are assembled
+// simultaneously. A candidate is dropped when a header byte doesn't match, or when its CRC fails,
+// but the bytes it covered stay buffered - so a real header hiding inside a corrupt frame still
+// gets decoded. The first candidate to complete with a valid CRC wins, and everything up to the
+// end of it is discarded (including any candidates which overlapped it).
+// Only MAX_CANDIDATES are assembled at once, so after a burst of fake headers the rest of the buffer
+// is searched as the earlier candidates fail.
+#[derive(Debug)]
+pub struct FrameParser {
+    buffer: [u8; BUFFER_LENGTH], // bytes which have been pushed, but not yet decoded or discarded
+    length: usize,
+    scanned: usize, // buffer position up to which headers have been made into candidates
+    candidates: [(usize, u128); MAX_CANDIDATES], // (buffer position, timestamp) of every header which might still become a frame
+    candidate_count: usize,
+    chunks: [(usize, u128); MAX_CHUNKS], // (end position, timestamp) of the pushes not yet fully scanned
+    chunk_count: usize,
+    sync: Sync,
+    stats: DecoderStats,
+    rate_snapshot: Option<(u128, DecoderStats)>, // (timestamp, stats) at the start of the current rate window
+}
+
+impl Default for FrameParser {
+    fn default() -> Self {
+        FrameParser {
+            buffer: [0; BUFFER_LENGTH],
+            length: 0,
+            scanned: 0,
+            candidates: [(0, 0); MAX_CANDIDATES],
+            candidate_count: 0,
+            chunks: [(0, 0); MAX_CHUNKS],
+            chunk_count: 0,
+            sync: Sync::Starting,
+            stats: DecoderStats::default(),
+            rate_snapshot: None,
+        }
+    }
+}
+
+impl FrameParser {
+    pub fn new() -> Self {
+        FrameParser::default()
+    }
+
+    // adds as many bytes from <data> as there is room for, returning how many were taken.
+    // <timestamp> is given to any frames whose header is in these bytes.
+    // Once next_frame has returned None there is always room for at least one more frame.
+    pub fn push(&mut self, data: &[u8], timestamp: u128) -> usize {
+        let n = data.len().min(BUFFER_LENGTH - self.length);
+        self.buffer[self.length..self.length + n].copy_from_slice(&data[..n]);
+        self.length += n;
+        self.stats.bytes_received += n as u64;
+
+        if n > 0 {
+            if self.chunk_count < MAX_CHUNKS {
+                self.chunks[self.chunk_count] = (self.length, timestamp);
+                self.chunk_count += 1;
+            } else {
+                // these bytes get the last remembered push's timestamp, which is a little early
+                self.chunks[MAX_CHUNKS - 1].0 = self.length;
+            }
+            self.scan();
+        }
+        n
+    }
+
+    // makes candidates of the headers after <scanned>, for as long as there are free slots
+    fn scan(&mut self) {
+        while self.scanned < self.length && self.candidate_count < MAX_CANDIDATES {
+            if self.buffer[self.scanned] == HEADER {
+                // chunks which have been fully scanned are removed below, so the first holds this byte
+                self.candidates[self.candidate_count] = (self.scanned, self.chunks[0].1);
+                self.candidate_count += 1;
+            }
+            self.scanned += 1;
+            self.forget_chunks();
+        }
+        if self.scanned < self.length {
+            debug!("too many candidate headers, searching the rest once some have failed");
+        }
+    }
+
+    // forgets the timestamps of pushes whose bytes have all been scanned
+    fn forget_chunks(&mut self) {
+        let scanned = self.chunks[..self.chunk_count].iter().take_while(|(end, _)| *end <= self.scanned).count();
+        if scanned > 0 {
+            self.chunks.copy_within(scanned..self.chunk_count, 0);
+            self.chunk_count -= scanned;
+        }
+    }
+
+    pub fn stats(&self) -> &DecoderStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = DecoderStats::default();
+        self.rate_snapshot = None;
+    }
+
+    // number of bytes waiting to be decoded
+    pub fn buffered(&self) -> usize {
+        self.length
+    }
+
+    pub fn clear(&mut self) {
+        self.skip(self.length);
+    }
+
+    // decodes the next complete frame out of the buffered bytes, if there is one.
+    pub fn next_frame(&mut self) -> Option<RawFrame> {
+        // candidates are in stream order, and come before any headers not yet scanned,
+        // so the first complete one is the oldest frame
+        let mut found: Option<(usize, usize, u128)> = None;
+        loop {
+            let mut i = 0;
+            while i < self.candidate_count {
+                let (start, timestamp) = self.candidates[i];
+                match self.check_candidate(start) {
+                    Candidate::Pending => i += 1,
+                    Candidate::Complete(length) => {
+                        found = Some((start, length, timestamp));
+                        break;
+                    }
+                    Candidate::BadHeader => self.remove_candidate(i),
+                    Candidate::CrcMismatch => {
+                        debug!("crc mismatch, rescanning frame for headers");
+                        self.stats.crc_failures += 1;
+                        self.remove_candidate(i);
+                    }
+                }
+            }
+
+            // carry on with the headers which didn't fit, if any slots were freed up
+            if found.is_some() || self.scanned == self.length || self.candidate_count == MAX_CANDIDATES {
+                break;
+            }
+            self.scan();
+        }
+
+        match found {
+            Some((start, length, timestamp)) => {
+                let frame = RawFrame::from_bytes(&self.buffer[start..start + length], timestamp).expect("candidate was checked");
+
+                self.skip(start);
+                self.discard(length);
+
+                if self.sync == Sync::Lost {
+                    self.stats.header_resyncs += 1;
+                }
+                self.sync = Sync::Synced;
+                self.stats.frames_ok += 1;
+                if frame.is_measurement_type() {
+                    self.stats.measurement_frames += 1;
+                } else if frame.is_health_type() {
+                    self.stats.health_frames += 1;
+                }
+
+                Some(frame)
+            }
+            None => {
+                // nothing before the oldest candidate can ever be part of a frame
+                let keep_from = match self.candidate_count {
+                    0 => self.scanned,
+                    _ => self.candidates[0].0,
+                };
+                self.skip(keep_from);
+                None
+            }
+        }
+    }
+
+    fn check_candidate(&self, start: usize) -> Candidate {
+        let data = &self.buffer[start..self.length];
+
+        // check as much of the header as we have so far
+        let header_length = data.len().min(HEADER_LENGTH);
+        if !data[..header_length].iter().enumerate().all(|(i, b)| header_byte_valid(i, *b)) {
+            return Candidate::BadHeader;
+        }
+
+        if header_length < HEADER_LENGTH {
+            return Candidate::Pending;
+        }
+
+        // header, payload, crc
+        let payload_length = u16::from_be_bytes([data[6], data[7]]) as usize;
+        if payload_length > MAX_PAYLOAD_LENGTH {
+            return Candidate::BadHeader;
+        }
+        let frame_length = HEADER_LENGTH + payload_length + 2;
+
+        if data.len() < frame_length {
+            return Candidate::Pending;
+        }
+
+        let crc_expected = u16::from_be_bytes([data[frame_length - 2], data[frame_length - 1]]);
+        if checksum(&data[..frame_length - 2]) == crc_expected {
+            Candidate::Complete(frame_length)
+        } else {
+            Candidate::CrcMismatch
+        }
+    }
+
+    fn remove_candidate(&mut self, i: usize) {
+        self.candidates.copy_within(i + 1..self.candidate_count, i);
+        self.candidate_count -= 1;
+    }
+
+    // drops bytes which aren't part of any frame
+    fn skip(&mut self, n: usize) {
+        if n > 0 {
+            self.stats.bytes_discarded += n as u64;
+            if self.sync == Sync::Synced {
+                self.sync = Sync::Lost;
+            }
+            self.discard(n);
+        }
+    }
+
+    fn discard(&mut self, n: usize) {
+        self.buffer.copy_within(n..self.length, 0);
+        self.length -= n;
+        // a discarded frame may have run past the last scanned byte, and needn't be scanned
+        self.scanned = self.scanned.max(n) - n;
+        for chunk in self.chunks[..self.chunk_count].iter_mut() {
+            chunk.0 = chunk.0.max(n) - n;
+        }
+        self.forget_chunks();
+
+        // forget about candidates which are no longer buffered, and move the rest along
+        let mut kept = 0;
+        for i in 0..self.candidate_count {
+            let (position, timestamp) = self.candidates[i];
+            if position >= n {
+                self.candidates[kept] = (position - n, timestamp);
+                kept += 1;
+            }
+        }
+        self.candidate_count = kept;
+    }
+
+    // recalculates the per-second rates, once at least a second has passed since the last time.
+    // should be called regularly, including when no data is arriving, so the rates drop to zero.
+    pub fn update_rates(&mut self, timestamp: u128) {
+        let (start, snapshot) = match &self.rate_snapshot {
+            Some(s) => s,
+            None => {
+                self.rate_snapshot = Some((timestamp, self.stats.clone()));
+                return;
+            }
+        };
+
+        let elapsed = timestamp.saturating_sub(*start);
+        if elapsed < 1_000_000_000 {
+            return;
+        }
+
+        let seconds = (elapsed as f64 / 1e9) as f32;
+        let rate = |now: u64, then: u64| ((now - then) as f32) / seconds;
+        self.stats.byte_rate = rate(self.stats.bytes_received, snapshot.bytes_received);
+        self.stats.frame_rate = rate(self.stats.frames_ok, snapshot.frames_ok);
+        self.stats.crc_failure_rate = rate(self.stats.crc_failures, snapshot.crc_failures);
+        self.stats.discard_rate = rate(self.stats.bytes_discarded, snapshot.bytes_discarded);
+
+        self.rate_snapshot = Some((timestamp, self.stats.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> RawFrame {
+        let measurements: [Measurement; 12] = core::array::from_fn(|_| Measurement { angle: 0.0, signal_quality: 200, distance_mm: 1000.0 });
+        RawFrame::encode_measurements(300.0, 24.0, &measurements, 0).unwrap()
+    }
+
+    fn decode_all(parser: &mut FrameParser, data: &[u8]) -> usize {
+        let mut frames = 0;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let n = parser.push(rest, 0);
+            rest = &rest[n..];
+            while parser.next_frame().is_some() {
+                frames += 1;
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn joining_the_stream_is_not_a_resync() {
+        let mut parser = FrameParser::new();
+        // starting part-way through a frame
+        let mut data = frame().as_bytes()[40..].to_vec();
+        data.extend_from_slice(frame().as_bytes());
+        data.extend_from_slice(frame().as_bytes());
+        assert_eq!(decode_all(&mut parser, &data), 2);
+        assert_eq!(parser.stats().header_resyncs, 0);
+
+        // then losing it
+        let mut data = vec![0x12, 0x34, 0x56];
+        data.extend_from_slice(frame().as_bytes());
+        assert_eq!(decode_all(&mut parser, &data), 1);
+        assert_eq!(parser.stats().header_resyncs, 1);
+    }
+
+    #[test]
+    fn frames_after_a_burst_of_fake_headers_are_found() {
+        let mut parser = FrameParser::new();
+        // many more fake headers than candidates, then frames, pushed together
+        let mut data = [HEADER; 3 * MAX_CANDIDATES].to_vec();
+        for _ in 0..3 {
+            data.extend_from_slice(frame().as_bytes());
+        }
+        assert_eq!(decode_all(&mut parser, &data), 3);
+
+        // frames only scanned once the fake headers have failed still get the timestamp of their push
+        assert_eq!(parser.push(&[HEADER; 3 * MAX_CANDIDATES], 1), 3 * MAX_CANDIDATES);
+        assert_eq!(parser.push(frame().as_bytes(), 2), frame().as_bytes().len());
+        assert_eq!(parser.next_frame().map(|f| f.timestamp), Some(2));
+        assert!(parser.next_frame().is_none());
+    }
+}
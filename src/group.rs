@@ -0,0 +1,585 @@
+// Runs several lidars together (e.g. one at each end of a robot). Their frames are merged into one
+// stream ordered by timestamp, and their revolutions are paired up into GroupScans, with each lidar's
+// points moved into the robot's frame by its mounting pose.
+//
+// let mut group = LidarGroup::new();
+// group.add("front", front_lidar, SensorPose::new(0.3, 0.0, 0.0))?;
+// group.add("rear", rear_lidar, SensorPose::new(-0.3, 0.0, 180.0))?;
+// group.start()?;
+// let scan = group.recv_scan()?;
+use crate::error::{Error, Result};
+use crate::lidar::{Lidar, LidarEvent};
+use crate::pose::SensorPose;
+use crate::protocol::{FullScan, ScanAssembler};
+use crate::queue::{queue, Droppable, OverflowPolicy, QueueReceiver, QueueSender};
+
+use itertools::Itertools;
+use log::{error, warn};
+use serde::Serialize;
+
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::BinaryHeap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how often the forwarding threads check whether the group is closing
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// An event from one of the lidars in the group
+#[derive(Debug, Clone)]
+pub struct GroupEvent {
+    pub device: String,
+    pub event: LidarEvent,
+}
+
+// One revolution from one lidar
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceScan {
+    pub device: String,
+    pub scan: FullScan, // its frames carry the lidar's pose
+}
+
+// One revolution from every connected lidar in the group, received within max_skew of each other
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GroupScan {
+    pub scans: Vec<DeviceScan>,
+}
+
+impl GroupScan {
+    pub fn as_json(&self) -> String {
+        serde_json::to_string(&self).expect("Serialized to JSON")
+    }
+
+    // the points of every lidar, in the robot's frame
+    pub fn points(&self) -> Vec<(f32,f32)> {
+        self.scans.iter().flat_map(|s| s.scan.points()).collect_vec()
+    }
+
+    pub fn timestamp(&self) -> u128 {
+        self.scans.iter().map(|s| s.scan.timestamp()).max().unwrap_or(0)
+    }
+
+    // max - min timestamp of the lidars' scans
+    pub fn timestamp_range(&self) -> i64 {
+        let min = self.scans.iter().map(|s| s.scan.timestamp()).min().unwrap_or(0);
+        (self.timestamp() as i64) - (min as i64)
+    }
+}
+
+impl Display for GroupScan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scans = self.scans.iter().map(|s| format!("{}: {}", s.device, s.scan)).join(", ");
+        f.write_str(&format!("{} lidars ({})", self.scans.len(), scans))
+    }
+}
+
+struct Member {
+    id: String,
+    pose: SensorPose,
+    // handed to its forwarding thread by start
+    lidar: Option<Lidar>,
+    // false between Disconnected and Reconnected, while scans are combined without it
+    connected: bool,
+    scans: ScanAssembler,
+    // its latest revolution, waiting for the other lidars to finish theirs
+    pending: Option<FullScan>,
+}
+
+// What the forwarding threads send to the merge thread
+enum Input {
+    Event(usize, LidarEvent),
+    Finished(usize), // the lidar's worker has stopped, so nothing more will come from it
+}
+
+// the merged stream, tagged with the member's index
+impl Droppable for (usize, LidarEvent) {
+    fn droppable(&self) -> bool {
+        self.1.droppable()
+    }
+}
+
+pub struct LidarGroup {
+    members: Vec<Member>,
+    rx: Option<QueueReceiver<(usize, LidarEvent)>>,
+    // one thread per lidar, forwarding its events to the merge thread
+    forwarders: Vec<thread::JoinHandle<Result<()>>>,
+    merger: Option<thread::JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+    merge_window: Duration,
+    max_skew: Duration,
+}
+
+impl Default for LidarGroup {
+    fn default() -> Self {
+        LidarGroup {
+            members: vec![],
+            rx: None,
+            forwarders: vec![],
+            merger: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            merge_window: Duration::from_millis(50),
+            max_skew: Duration::from_millis(150),
+        }
+    }
+}
+
+impl LidarGroup {
+    pub fn new() -> LidarGroup {
+        LidarGroup::default()
+    }
+
+    // adds an opened lidar to the group, as <id>, mounted at <pose> (replacing any pose already set on it).
+    // Only possible before start.
+    pub fn add(&mut self, id: impl Into<String>, mut lidar: Lidar, pose: SensorPose) -> Result<()> {
+        let id = id.into();
+        if self.started() {
+            return Err(Error::InvalidParameter("lidars can't be added to a group once it has started".to_string()));
+        }
+        if self.members.iter().any(|m| m.id == id) {
+            return Err(Error::InvalidParameter(format!("there is already a lidar called '{}' in the group", id)));
+        }
+        lidar.set_pose(pose);
+        self.members.push(Member { id, pose, lidar: Some(lidar), connected: true, scans: ScanAssembler::new(), pending: None });
+        Ok(())
+    }
+
+    // opens the serial port (or endpoint URI) at <path>, and adds it to the group
+    pub fn open(&mut self, id: impl Into<String>, path: String, pose: SensorPose) -> Result<()> {
+        let mut lidar = Lidar::new();
+        lidar.open(path)?;
+        self.add(id, lidar, pose)
+    }
+
+    pub fn ids(&self) -> Vec<String> {
+        self.members.iter().map(|m| m.id.clone()).collect_vec()
+    }
+
+    pub fn pose(&self, id: &str) -> Option<SensorPose> {
+        self.members.iter().find(|m| m.id == id).map(|m| m.pose)
+    }
+
+    // how long a frame is held back waiting for frames with earlier timestamps from the other lidars.
+    // Only a lidar which has gone quiet makes the others wait this long. Takes effect on the next start.
+    pub fn set_merge_window(&mut self, window: Duration) {
+        self.merge_window = window;
+    }
+
+    // the most the lidars' revolutions in a GroupScan can be apart. A revolution which is further behind
+    // the others than this is dropped, and its lidar's next one is used instead.
+    pub fn set_max_skew(&mut self, skew: Duration) {
+        self.max_skew = skew;
+    }
+
+    // starts merging the lidars' events
+    pub fn start(&mut self) -> Result<()> {
+        if self.started() {
+            return Err(Error::InvalidParameter("group has already been started".to_string()));
+        }
+        if self.members.is_empty() {
+            return Err(Error::InvalidParameter("group has no lidars in it".to_string()));
+        }
+
+        let (input_tx, input_rx) = mpsc::channel();
+        let (tx, rx) = queue(None, OverflowPolicy::default());
+        self.rx = Some(rx);
+        self.stop = Arc::new(AtomicBool::new(false));
+
+        for (index, member) in self.members.iter_mut().enumerate() {
+            let lidar = member.lidar.take().expect("lidar is only taken by start");
+            let input_tx = input_tx.clone();
+            let stop = self.stop.clone();
+            self.forwarders.push(
+                thread::Builder::new()
+                    .name(format!("lidar_group_{}", member.id))
+                    .spawn(move || forward(index, lidar, input_tx, stop))?,
+            );
+        }
+
+        let merger = Merger::new(self.members.len(), self.merge_window);
+        self.merger = Some(
+            thread::Builder::new()
+                .name("lidar_group_merge".to_string())
+                .spawn(move || merger.run(input_rx, tx))?,
+        );
+        Ok(())
+    }
+
+    // the lidars are handed to their threads by start, and closed with the group, so it can only start once
+    fn started(&self) -> bool {
+        self.members.iter().any(|m| m.lidar.is_none())
+    }
+
+    // stops every lidar in the group, returning the first error any of them stopped with
+    pub fn close(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.rx = None;
+
+        let mut result = Ok(());
+        for th in self.forwarders.drain(..) {
+            let r = th.join().unwrap_or(Err(Error::WorkerPanicked));
+            if let Err(e) = r {
+                warn!("Lidar in group exited with: {}", e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        if let Some(th) = self.merger.take() {
+            if th.join().is_err() && result.is_ok() {
+                result = Err(Error::WorkerPanicked);
+            }
+        }
+        result
+    }
+
+    // true while any of the lidars is still running
+    pub fn alive(&self) -> bool {
+        self.merger.as_ref().map(|th| !th.is_finished()).unwrap_or(false)
+    }
+
+    // the next event from any of the lidars, in timestamp order
+    pub fn recv_event(&mut self) -> Result<GroupEvent> {
+        self.recv_event_until(None)
+    }
+
+    pub fn recv_event_timeout(&mut self, timeout: Duration) -> Result<GroupEvent> {
+        self.recv_event_until(Instant::now().checked_add(timeout))
+    }
+
+    fn recv_event_until(&mut self, deadline: Option<Instant>) -> Result<GroupEvent> {
+        let (index, event) = self.recv_indexed(deadline)?;
+        Ok(GroupEvent { device: self.members[index].id.clone(), event })
+    }
+
+    fn recv_indexed(&mut self, deadline: Option<Instant>) -> Result<(usize, LidarEvent)> {
+        let rx = self.rx.as_ref().ok_or(Error::NotOpened)?;
+
+        let result = match deadline {
+            Some(d) => rx.recv_timeout(d.saturating_duration_since(Instant::now())),
+            None => rx.recv(),
+        };
+
+        match result {
+            Ok(e) => Ok(e),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            // the merge thread only exits once every lidar has stopped
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Disconnected),
+        }
+    }
+
+    // returns the next revolution from every connected lidar, paired up by timestamp.
+    // A lidar which is reconnecting (or has stopped) is left out until it's back.
+    pub fn recv_scan(&mut self) -> Result<GroupScan> {
+        self.recv_scan_until(None)
+    }
+
+    pub fn recv_scan_timeout(&mut self, timeout: Duration) -> Result<GroupScan> {
+        self.recv_scan_until(Instant::now().checked_add(timeout))
+    }
+
+    fn recv_scan_until(&mut self, deadline: Option<Instant>) -> Result<GroupScan> {
+        loop {
+            let (index, event) = self.recv_indexed(deadline)?;
+            let member = &mut self.members[index];
+
+            match event {
+                LidarEvent::Measurement(f) => {
+                    match member.scans.push(f) {
+                        Some(scan) => member.pending = Some(scan),
+                        None => continue,
+                    }
+                },
+                LidarEvent::Health(_) => continue,
+                LidarEvent::Disconnected => {
+                    member.connected = false;
+                    member.scans.reset();
+                    member.pending = None;
+                },
+                LidarEvent::Reconnected => {
+                    member.connected = true;
+                    continue;
+                },
+            }
+
+            if let Some(scan) = self.take_aligned() {
+                return Ok(scan);
+            }
+        }
+    }
+
+    // takes the pending revolutions, if every connected lidar has one, and they're close enough together
+    fn take_aligned(&mut self) -> Option<GroupScan> {
+        let mut connected = self.members.iter_mut().filter(|m| m.connected).collect_vec();
+        if connected.is_empty() || connected.iter().any(|m| m.pending.is_none()) {
+            return None;
+        }
+
+        let timestamp = |m: &Member| m.pending.as_ref().map(|s| s.timestamp()).unwrap_or(0);
+        let newest = connected.iter().map(|m| timestamp(m)).max().unwrap_or(0);
+        let mut aligned = true;
+        for m in connected.iter_mut() {
+            if timestamp(m) + self.max_skew.as_nanos() < newest {
+                // wait for this lidar's next revolution instead
+                m.pending = None;
+                aligned = false;
+            }
+        }
+        if !aligned {
+            return None;
+        }
+
+        Some(GroupScan {
+            scans: connected
+                .into_iter()
+                .map(|m| DeviceScan { device: m.id.clone(), scan: m.pending.take().expect("checked above") })
+                .collect_vec(),
+        })
+    }
+}
+
+impl Drop for LidarGroup {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            warn!("Lidar group exited with: {}", e);
+        }
+    }
+}
+
+// passes the events of one lidar on to the merge thread, until the group closes or the lidar stops.
+// the lidar is closed on the way out, returning the result its worker exited with.
+fn forward(index: usize, mut lidar: Lidar, input: mpsc::Sender<Input>, stop: Arc<AtomicBool>) -> Result<()> {
+    let result = loop {
+        if stop.load(Ordering::SeqCst) {
+            break Ok(());
+        }
+        match lidar.recv_event_timeout(POLL_INTERVAL) {
+            Ok(e) => {
+                if input.send(Input::Event(index, e)).is_err() {
+                    break Ok(());
+                }
+            },
+            Err(Error::Timeout) => {},
+            Err(e) => {
+                error!("Lidar {} in group stopped: {}", index, e);
+                // lets scans carry on without it
+                let _ = input.send(Input::Event(index, LidarEvent::Disconnected));
+                break Err(e);
+            },
+        }
+    };
+    let _ = input.send(Input::Finished(index));
+    lidar.close().and(result)
+}
+
+// An event waiting to be put in order, sorted by timestamp (earliest first, as BinaryHeap is a max-heap)
+struct Held {
+    timestamp: u128,
+    sequence: u64, // keeps events with the same timestamp in the order they arrived
+    received: Instant,
+    index: usize,
+    event: LidarEvent,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (other.timestamp, other.sequence).cmp(&(self.timestamp, self.sequence))
+    }
+}
+
+// Puts the events of every lidar into timestamp order. An event is passed on once every other
+// (running) lidar has sent something later, or after merge_window, whichever comes first.
+struct Merger {
+    held: BinaryHeap<Held>,
+    latest: Vec<u128>, // timestamp of the last frame from each lidar
+    finished: Vec<bool>,
+    sequence: u64,
+    merge_window: Duration,
+}
+
+impl Merger {
+    fn new(devices: usize, merge_window: Duration) -> Self {
+        Merger {
+            held: BinaryHeap::new(),
+            latest: vec![0; devices],
+            finished: vec![false; devices],
+            sequence: 0,
+            merge_window,
+        }
+    }
+
+    fn run(mut self, input: mpsc::Receiver<Input>, output: QueueSender<(usize, LidarEvent)>) {
+        loop {
+            let deadline = match self.held.peek() {
+                Some(h) => h.received.checked_add(self.merge_window),
+                None => Instant::now().checked_add(POLL_INTERVAL),
+            };
+            let result = match deadline {
+                Some(d) => input.recv_timeout(d.saturating_duration_since(Instant::now())),
+                // a merge window too long to represent never runs out
+                None => input.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match result {
+                Ok(Input::Event(index, event)) => self.hold(index, event),
+                Ok(Input::Finished(index)) => self.finished[index] = true,
+                Err(RecvTimeoutError::Timeout) => {},
+                // every lidar has stopped
+                Err(RecvTimeoutError::Disconnected) => {
+                    while let Some(h) = self.held.pop() {
+                        let _ = output.send((h.index, h.event));
+                    }
+                    return;
+                },
+            }
+
+            while let Some(h) = self.held.peek() {
+                if !self.ready(h) {
+                    break;
+                }
+                let h = self.held.pop().expect("peeked");
+                if output.send((h.index, h.event)).is_err() {
+                    // nobody is listening any more
+                    return;
+                }
+            }
+        }
+    }
+
+    fn hold(&mut self, index: usize, event: LidarEvent) {
+        let timestamp = match &event {
+            LidarEvent::Measurement(f) => f.timestamp,
+            LidarEvent::Health(h) => h.timestamp,
+            // keep connection events in place among the lidar's own frames
+            LidarEvent::Disconnected | LidarEvent::Reconnected => self.latest[index],
+        };
+        self.latest[index] = self.latest[index].max(timestamp);
+        self.sequence += 1;
+        self.held.push(Held { timestamp, sequence: self.sequence, received: Instant::now(), index, event });
+    }
+
+    fn ready(&self, held: &Held) -> bool {
+        if held.received.elapsed() >= self.merge_window {
+            return true;
+        }
+        // nothing earlier can still arrive from the other lidars
+        (0..self.latest.len())
+            .filter(|i| *i != held.index && !self.finished[*i])
+            .all(|i| self.latest[i] >= held.timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::MeasurementFrame;
+
+    fn frame(timestamp: u128) -> LidarEvent {
+        LidarEvent::Measurement(MeasurementFrame { timestamp, ..MeasurementFrame::default() })
+    }
+
+    fn timestamp(event: &LidarEvent) -> u128 {
+        match event {
+            LidarEvent::Measurement(f) => f.timestamp,
+            _ => panic!("expected a measurement frame"),
+        }
+    }
+
+    // runs a Merger for two lidars on its own thread
+    fn merger(merge_window: Duration) -> (mpsc::Sender<Input>, QueueReceiver<(usize, LidarEvent)>, thread::JoinHandle<()>) {
+        let (input_tx, input_rx) = mpsc::channel();
+        let (tx, rx) = queue(None, OverflowPolicy::default());
+        let merger = Merger::new(2, merge_window);
+        (input_tx, rx, thread::spawn(move || merger.run(input_rx, tx)))
+    }
+
+    #[test]
+    fn merger_orders_frames_by_timestamp() {
+        let (input, output, worker) = merger(Duration::from_millis(200));
+        for (index, timestamp) in [(0, 200), (1, 100), (1, 300), (0, 400)] {
+            input.send(Input::Event(index, frame(timestamp))).unwrap();
+        }
+
+        // each is passed on once the other lidar has sent something later
+        for (index, expected) in [(1, 100), (0, 200), (1, 300)] {
+            let (i, event) = output.recv_timeout(Duration::from_millis(100)).unwrap();
+            assert_eq!((i, timestamp(&event)), (index, expected));
+        }
+
+        // and the last, which nothing came after, once the merge window is over
+        let started = Instant::now();
+        assert!(output.recv_timeout(Duration::from_millis(50)).is_err());
+        let (i, event) = output.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!((i, timestamp(&event)), (0, 400));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        drop(input);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn merger_with_an_endless_window_flushes_when_stopped() {
+        let (input, output, worker) = merger(Duration::MAX);
+        input.send(Input::Event(0, frame(100))).unwrap();
+        input.send(Input::Event(0, frame(200))).unwrap();
+        assert!(output.recv_timeout(Duration::from_millis(50)).is_err());
+
+        drop(input);
+        worker.join().unwrap();
+        let timestamps = std::iter::from_fn(|| output.recv_timeout(Duration::ZERO).ok()).map(|(_, e)| timestamp(&e)).collect_vec();
+        assert_eq!(timestamps, vec![100, 200]);
+    }
+
+    fn with_pending(pending: &[Option<u128>]) -> LidarGroup {
+        let mut group = LidarGroup::new();
+        for (i, timestamp) in pending.iter().enumerate() {
+            let pending = timestamp.map(|t| FullScan { frames: vec![MeasurementFrame { timestamp: t, ..MeasurementFrame::default() }] });
+            group.members.push(Member { id: i.to_string(), pose: SensorPose::default(), lidar: None, connected: true, scans: ScanAssembler::new(), pending });
+        }
+        group
+    }
+
+    #[test]
+    fn aligned_scans_are_taken_together() {
+        let mut group = with_pending(&[Some(1_000_000_000), Some(1_100_000_000)]);
+        let scan = group.take_aligned().unwrap();
+        assert_eq!(scan.scans.iter().map(|s| s.device.as_str()).collect_vec(), vec!["0", "1"]);
+        assert!(group.members.iter().all(|m| m.pending.is_none()));
+
+        // not until every connected lidar has one
+        let mut group = with_pending(&[Some(1_000_000_000), None]);
+        assert!(group.take_aligned().is_none());
+        group.members[1].connected = false;
+        assert_eq!(group.take_aligned().unwrap().scans.len(), 1);
+    }
+
+    #[test]
+    fn scans_older_than_the_max_skew_are_dropped() {
+        // 150ms by default
+        let mut group = with_pending(&[Some(1_000_000_000), Some(1_200_000_000)]);
+        assert!(group.take_aligned().is_none());
+        assert!(group.members[0].pending.is_none());
+        assert!(group.members[1].pending.is_some());
+
+        let mut group = with_pending(&[Some(1_000_000_000), Some(1_200_000_000)]);
+        group.set_max_skew(Duration::from_millis(250));
+        assert!(group.take_aligned().is_some());
+    }
+}
@@ -1,23 +1,107 @@
-use crate::protocol::{MeasurementFrame, PartialFrame, FullScan};
+use crate::error::{Error, Result};
+use crate::protocol::{DecoderStats, MeasurementFrame, HealthFrame, PartialFrame, FrameDecoder, FullScan, ScanAssembler};
 
-use anyhow::Result;
-use log::{debug, error, warn, info};
+use log::{error, info, warn};
 
-use serialport::{self, SerialPort};
+use std::fs::File;
+use std::io::{self, BufWriter};
 
-use std::io::{self, Write};
+use crate::queue::{queue, Droppable, OverflowPolicy, QueueReceiver, QueueSender};
+use crate::capture::CaptureWriter;
+use crate::config::LidarConfig;
+use crate::discover::{discover, DiscoveredPort, DiscoveryOptions};
+use crate::pose::SensorPose;
+use crate::transport::{Endpoint, Transport};
 
-use std::sync::mpsc::channel;
-use std::sync::mpsc::{self, RecvError};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+pub const BAUD_RATE: u32 = 115200;
+
+// Everything the lidar can send us, decoded into the matching frame type.
+// Disconnected/Reconnected are only sent when a ReconnectPolicy is set.
+#[derive(Debug, Clone)]
+pub enum LidarEvent {
+    Measurement(MeasurementFrame),
+    Health(HealthFrame),
+    Disconnected,
+    Reconnected,
+}
+
+// a full queue throws away frames, but the connection events always get through
+impl Droppable for LidarEvent {
+    fn droppable(&self) -> bool {
+        matches!(self, LidarEvent::Measurement(_) | LidarEvent::Health(_))
+    }
+}
+
+// Opt-in policy for re-opening the serial port after it fails (e.g. USB unplug/replug).
+// The worker waits retry_interval before the first attempt, doubling the wait after
+// every failed attempt, up to max_backoff. It gives up after max_attempts, if set.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub retry_interval: Duration,
+    pub max_backoff: Duration,
+    pub max_attempts: Option<u32>,
+}
+
+impl From<PartialFrame> for LidarEvent {
+    fn from(value: PartialFrame) -> Self {
+        // the decoder only hands out frames with a valid CRC, and a known command
+        if value.is_health_type() {
+            LidarEvent::Health(value.into())
+        } else {
+            LidarEvent::Measurement(value.into())
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            retry_interval: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            max_attempts: None,
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Lidar {
-    pub rx: Option<mpsc::Receiver<PartialFrame>>,
+    pub rx: Option<QueueReceiver<LidarEvent>>,
     // A handle to the background receiver thread is put here
     worker_handle: Option<thread::JoinHandle<Result<()>>>,
+    // The worker thread keeps a copy of its decoder statistics here
+    stats: Arc<Mutex<DecoderStats>>,
+    reconnect: Option<ReconnectPolicy>,
+    // Size of the event queue (None is unbounded), and what to do when it's full
+    queue_capacity: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    // Set to ask the worker thread to exit
+    stop: Arc<AtomicBool>,
+    // Groups frames into revolutions for recv_fullscan, keeping the current one if it times out part-way through
+    scans: ScanAssembler,
+    // While recording, the worker thread also writes everything it reads in here
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    // Port and worker thread settings, used by every open (and reconnect) from then on
+    config: LidarConfig,
+    // Where the lidar is mounted, put on every frame received
+    pose: SensorPose,
+}
+
+type Recorder = CaptureWriter<BufWriter<File>>;
+
+// What the worker thread shares with the Lidar
+struct Worker {
+    tx: QueueSender<LidarEvent>,
+    stats: Arc<Mutex<DecoderStats>>,
+    stop: Arc<AtomicBool>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    read_buffer_size: usize,
 }
 
 impl Lidar {
@@ -27,8 +111,14 @@ impl Lidar {
         Lidar::default()
     }
 
+    // finds the serial ports with a Delta-2 attached, by listening for valid frames on each of them.
+    // see discover.rs for more options, like filtering by USB id.
+    pub fn discover() -> Result<Vec<DiscoveredPort>> {
+        discover(&DiscoveryOptions::default())
+    }
+
     pub fn alive(&self) -> bool {
-        // checks if worker thread is alive, and the channels exist.
+        // checks if worker thread is alive, and the queue exists.
         match self.rx.is_some() && self.worker_handle.is_some() {
             true => {
                 let th : &thread::JoinHandle<Result<()>> = self.worker_handle.as_ref().unwrap();
@@ -38,145 +128,407 @@ impl Lidar {
         }
     }
 
-    pub fn recv(&mut self) -> Result<MeasurementFrame, RecvError> {
-        match self.alive() {
-            true => match self.rx.as_ref().unwrap().recv() {
-                Ok(m) => {
-                    if m.is_measurement_type() {
-                        Ok(m.into())
-                    } else {
-                        Err(RecvError)
-                    }
-                }
-                Err(_) => Err(RecvError),
+    // sets the policy used to re-open the port if it fails. Takes effect on the next open.
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect = policy;
+    }
+
+    // bounds the event queue to <capacity> events (or unbounded with None), with <policy> deciding
+    // what happens when the consumer falls behind. Takes effect on the next open.
+    pub fn set_queue(&mut self, capacity: Option<usize>, policy: OverflowPolicy) {
+        self.queue_capacity = capacity;
+        self.overflow_policy = policy;
+    }
+
+    // sets where the lidar is mounted on the robot, so that points() gives points in the robot's frame.
+    // Applies to every frame received from now on.
+    pub fn set_pose(&mut self, pose: SensorPose) {
+        self.pose = pose;
+    }
+
+    pub fn pose(&self) -> SensorPose {
+        self.pose
+    }
+
+    // number of frames dropped because the queue was full
+    pub fn dropped_frames(&self) -> u64 {
+        self.rx.as_ref().map(|rx| rx.dropped()).unwrap_or(0)
+    }
+
+    pub fn recv(&mut self) -> Result<MeasurementFrame> {
+        self.recv_until(None)
+    }
+
+    // returns a frame if one is already waiting, otherwise Error::Timeout.
+    pub fn try_recv(&mut self) -> Result<MeasurementFrame> {
+        self.recv_until(Some(Instant::now()))
+    }
+
+    // like recv, but gives up with Error::Timeout after <timeout>.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<MeasurementFrame> {
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    // like recv, but also returns the health frames which the lidar sends when it can't measure,
+    // and the connection events when reconnecting.
+    pub fn recv_event(&mut self) -> Result<LidarEvent> {
+        self.recv_event_until(None)
+    }
+
+    pub fn recv_event_timeout(&mut self, timeout: Duration) -> Result<LidarEvent> {
+        self.recv_event_until(Instant::now().checked_add(timeout))
+    }
+
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<MeasurementFrame> {
+        loop {
+            match self.recv_event_until(deadline)? {
+                LidarEvent::Measurement(m) => return Ok(m),
+                LidarEvent::Health(h) => return Err(Error::HealthFault(h)),
+                LidarEvent::Disconnected => return Err(Error::Disconnected),
+                LidarEvent::Reconnected => continue,
+            }
+        }
+    }
+
+    // waits for the next event, forever if there's no deadline.
+    fn recv_event_until(&mut self, deadline: Option<Instant>) -> Result<LidarEvent> {
+        let rx = self.rx.as_ref().ok_or(Error::NotOpened)?;
+
+        let result = match deadline {
+            Some(d) => rx.recv_timeout(d.saturating_duration_since(Instant::now())),
+            None => rx.recv(),
+        };
+
+        match result {
+            Ok(LidarEvent::Measurement(mut f)) => {
+                f.pose = self.pose;
+                Ok(LidarEvent::Measurement(f))
+            },
+            Ok(e) => Ok(e),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            // the sender only goes away when the worker thread exits
+            Err(RecvTimeoutError::Disconnected) => Err(self.worker_error()),
+        }
+    }
+
+    // returns a snapshot of the decoder statistics
+    pub fn stats(&self) -> DecoderStats {
+        self.stats.lock().expect("stats lock poisoned").clone()
+    }
+
+    // starts writing every chunk of bytes received, with its receive timestamp, into a capture file
+    // at <path> (see capture.rs), which open_replay can play back with the original timing.
+    // Carries on across reconnects and re-opens, and replaces any recording already in progress.
+    pub fn start_recording(&mut self, path: String) -> Result<()> {
+        let writer = CaptureWriter::new(BufWriter::new(File::create(&path)?))?;
+        let previous = self.recorder.lock().expect("recorder lock poisoned").replace(writer);
+        if let Some(mut p) = previous {
+            p.flush()?;
+        }
+        info!("Recording to {}", path);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> Result<()> {
+        let recorder = self.recorder.lock().expect("recorder lock poisoned").take();
+        if let Some(mut r) = recorder {
+            r.flush()?;
+        }
+        Ok(())
+    }
+
+    // false once stop_recording is called, or if writing to the capture file failed
+    pub fn recording(&self) -> bool {
+        self.recorder.lock().expect("recorder lock poisoned").is_some()
+    }
+
+    // stops the worker thread, waits for it to exit (releasing the serial port),
+    // and returns the result it exited with.
+    pub fn close(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.rx = None;
+
+        match self.worker_handle.take() {
+            Some(th) => match th.join() {
+                Ok(r) => r,
+                Err(_) => Err(Error::WorkerPanicked),
+            },
+            None => Ok(()),
+        }
+    }
+
+    // collects the reason the worker thread stopped.
+    fn worker_error(&mut self) -> Error {
+        match self.worker_handle.take() {
+            Some(th) => match th.join() {
+                Ok(Ok(())) => Error::Disconnected,
+                Ok(Err(e)) => e,
+                Err(_) => Error::WorkerPanicked,
             },
-            false => Err(RecvError),
+            None => Error::Disconnected,
         }
     }
 
-    pub fn recv_fullscan(&mut self) -> Result<FullScan, RecvError> {
-        // internally, makes calls to self.recv, until it has built up a complete FullScan message.
-        let mut fs = FullScan::default();
+    // returns the next revolution of the lidar, made of one frame per sector.
+    // sectors may be missing if frames were lost, see FullScan::missing_sectors.
+    pub fn recv_fullscan(&mut self) -> Result<FullScan> {
+        self.recv_fullscan_until(None)
+    }
+
+    // like recv_fullscan, but gives up with Error::Timeout if the whole scan hasn't arrived after <timeout>.
+    pub fn recv_fullscan_timeout(&mut self, timeout: Duration) -> Result<FullScan> {
+        self.recv_fullscan_until(Instant::now().checked_add(timeout))
+    }
 
-        while !fs.complete() {
-            match self.recv() {
+    fn recv_fullscan_until(&mut self, deadline: Option<Instant>) -> Result<FullScan> {
+        // internally, makes calls to self.recv, until a whole revolution has been received.
+        // the frames received so far are kept on a timeout, so the next call carries on from there.
+        loop {
+            match self.recv_until(deadline) {
                 Ok(f) => {
-                    fs.frames.append(&mut vec![f.clone()]);
+                    if let Some(scan) = self.scans.push(f) {
+                        return Ok(scan);
+                    }
                 },
-                Err(_) => {
+                Err(Error::HealthFault(_)) => {
                     // ignore!
                 },
+                Err(Error::Disconnected) if self.alive() => {
+                    // the worker is reconnecting, so start the scan again afterwards
+                    self.scans.reset();
+                },
+                Err(e) => return Err(e),
             }
         }
-
-        Ok(fs)
     }
 
-    // attempts to bind to the serial port provided by <path>,
-    // if successful, sets up the message passing channel,
+    // attempts to bind to the serial port provided by <path>, or any other endpoint URI (see transport.rs),
+    // if successful, sets up the message passing queue,
     // and begins reading data in a background thread.
-    pub fn open(&mut self, path: String) -> Result<(), Box<dyn std::error::Error>> {
-        if self.alive() {
-            warn!("Lidar has already been opened! This may cause unexpected behaviour!");
+    pub fn open(&mut self, path: String) -> Result<()> {
+        self.open_endpoint(Endpoint::parse(&path)?)
+    }
+
+    // like open, with different port or worker thread settings (see config.rs),
+    // which are kept for later calls to open too.
+    pub fn open_with(&mut self, path: String, config: LidarConfig) -> Result<()> {
+        config.validate()?;
+        self.config = config;
+        self.open(path)
+    }
+
+    pub fn config(&self) -> &LidarConfig {
+        &self.config
+    }
+
+    // like open, but plays back a recording from the file at <path>, either a capture file written by
+    // start_recording, or a raw capture of the lidar's serial output (paced at 115200 baud).
+    // <speed> scales the playback rate, with 1.0 being the rate it was recorded at, and 0.0 as fast as possible.
+    // Once the whole file has been read, recv returns Error::Disconnected.
+    pub fn open_replay(&mut self, path: String, speed: f64) -> Result<()> {
+        if !(speed >= 0.0 && speed.is_finite()) {
+            return Err(Error::InvalidParameter(format!("invalid replay speed {}", speed)));
+        }
+        self.open_endpoint(Endpoint::File { path, speed })
+    }
+
+    // like open, reconnecting to the same endpoint if a reconnect policy is set
+    pub fn open_endpoint(&mut self, endpoint: Endpoint) -> Result<()> {
+        self.close_previous();
+
+        // Open it up-front, so that a bad path is reported straight away
+        let transport = endpoint.open(&self.config)?;
+        self.start(transport, Some(endpoint))
+    }
+
+    // reads from any Transport. As there's no way of reconnecting to it, errors always stop the worker.
+    pub fn open_transport(&mut self, transport: Box<dyn Transport>) -> Result<()> {
+        self.close_previous();
+        self.start(transport, None)
+    }
+
+    fn close_previous(&mut self) {
+        if self.worker_handle.is_some() {
+            warn!("Lidar has already been opened! Closing it first.");
+            if let Err(e) = self.close() {
+                warn!("Previous worker exited with: {}", e);
+            }
         }
+    }
 
-        // Channel is used to pass decoded frames from worker thread -> main thread
-        let (tx, rx) = channel();
+    // sets up the message passing queue, and starts reading <transport> in a background thread
+    fn start(&mut self, transport: Box<dyn Transport>, endpoint: Option<Endpoint>) -> Result<()> {
+        // Queue is used to pass decoded frames from worker thread -> main thread
+        let (tx, rx) = queue(self.queue_capacity, self.overflow_policy);
         self.rx = Some(rx);
+        self.scans.reset();
+
+        self.stop = Arc::new(AtomicBool::new(false));
+
+        let worker = Worker {
+            tx,
+            stats: self.stats.clone(),
+            stop: self.stop.clone(),
+            recorder: self.recorder.clone(),
+            read_buffer_size: self.config.read_buffer_size,
+        };
+        let reconnect = endpoint.zip(self.reconnect.clone());
+        let config = self.config.clone();
 
         self.worker_handle = Some(
             thread::Builder::new()
-                .name("lidar_decode_thread".to_string())
+                .name(config.thread_name.clone())
                 .spawn(move || {
-                    let port_name = path.as_str();
-                    let port_builder =
-                        serialport::new(port_name, 115200).timeout(Duration::from_millis(20));
-
-                    let port: Option<Box<dyn SerialPort>> = match port_builder.open() {
-                        Ok(p) => {
-                            // We can continue
-                            Some(p)
-                        }
-                        Err(err) => {
-                            // retry!
-                            panic!("{}", err);
-                        }
-                    };
-
-                    if let Some(mut serial) = port {
-                        let mut serial_data_buffer: Vec<u8> = vec![];
-                        let mut serial_temp_buf: Vec<u8> = vec![0; 256];
-
-                        // set the new frame
-                        let mut new_frame = PartialFrame::new();
-
-                        // continuously read new frames
-                        loop {
-                            match serial.read(serial_temp_buf.as_mut_slice()) {
-                                Ok(t) => {
-                                    // concat the serial read buffer onto the serial accumualted buffer
-                                    serial_data_buffer.append(&mut serial_temp_buf[..t].to_vec());
-
-                                    let j = serial_data_buffer.len(); // starting size of data buffer
-                                    let result = new_frame.write_all(&serial_data_buffer[..j]);
-                                    let written = new_frame.bytes_written;
-
-                                    // if we have a buffer full error, then we need to remove not 'j', but 'j-n' bytes from the data buffer.
-                                    let n = j - written;
-                                    debug!("avail = {}, written = {}, remain = {}", j, written, n);
-                                    serial_data_buffer =
-                                        serial_data_buffer.as_mut_slice()[..n].to_vec();
-
-                                    match result.as_ref() {
-                                        Ok(_) => {}
-                                        Err(err) => {
-                                            match err.raw_os_error() {
-                                                Some(105) => {
-                                                    // no space left in frame 'buffer'
-                                                    debug!(
-                                                        "Frame full. t = {}, frame = {}",
-                                                        t,
-                                                        new_frame.data.len()
-                                                    );
-                                                }
-                                                Some(_) => {}
-                                                None => {}
-                                            }
-                                        }
-                                    }
-
-                                    // if the frame is done
-                                    if new_frame.finished() {
-                                        let send_result = tx.send(new_frame.clone());
-
-                                        match send_result {
-                                            Ok(_) => {}
-                                            Err(e) => {
-                                                error!("{}", e);
-                                            }
-                                        };
-                                    }
-
-                                    // as mentioned, an error on the frame write result means it's buffer is full.
-                                    // so we reset.
-                                    if result.is_err() {
-                                        new_frame.reset();
-                                    }
-                                }
-                                // ignore timeout
-                                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
-                                // all other errors cause panic
-                                Err(e) => {
-                                    panic!("{}", e)
-                                }
-                            }
-                        }
+                    if let Some(nice) = config.thread_priority {
+                        set_thread_priority(nice);
                     }
 
-                    // all good! :D
-                    Ok(())
+                    // the decoder (and its statistics) lives across reconnects
+                    let mut decoder = FrameDecoder::new();
+                    let mut transport = transport;
+
+                    loop {
+                        let err = match read_until_error(&mut *transport, &mut decoder, &worker) {
+                            // asked to stop
+                            Ok(()) => return Ok(()),
+                            // the end of a recording
+                            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && transport.finite() => {
+                                info!("Reached the end of the data");
+                                return Ok(());
+                            },
+                            Err(e) => e,
+                        };
+                        error!("{}", err);
+
+                        let (endpoint, policy) = match reconnect.as_ref() {
+                            Some(r) => r,
+                            // errors stop the worker, and are reported by recv
+                            None => return Err(Error::Io(err)),
+                        };
+
+                        let _ = worker.tx.send(LidarEvent::Disconnected);
+                        // whatever was half-received is from the old connection
+                        decoder.clear();
+
+                        transport = match reconnect_to(endpoint, &config, policy, &worker.stop)? {
+                            Some(t) => t,
+                            None => return Ok(()),
+                        };
+                        info!("Reconnected to {}", endpoint);
+                        let _ = worker.tx.send(LidarEvent::Reconnected);
+                    }
                 })?,
         );
         Ok(())
     }
 }
+
+impl Drop for Lidar {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            warn!("Lidar worker exited with: {}", e);
+        }
+    }
+}
+
+// tries to open the endpoint again, following the backoff policy.
+// returns the last open error if the policy gives up, or None if asked to stop.
+fn reconnect_to(endpoint: &Endpoint, config: &LidarConfig, policy: &ReconnectPolicy, stop: &AtomicBool) -> Result<Option<Box<dyn Transport>>> {
+    let mut delay = policy.retry_interval;
+    let mut attempts: u32 = 0;
+
+    loop {
+        // sleep in small steps, so that close() doesn't have to wait for the whole backoff
+        // a delay too long to represent never ends
+        let wake = Instant::now().checked_add(delay);
+        while wake.map(|w| Instant::now() < w).unwrap_or(true) {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        attempts += 1;
+
+        match endpoint.open(config) {
+            Ok(t) => return Ok(Some(t)),
+            Err(e) => {
+                warn!("Reconnect attempt {} to {} failed: {}", attempts, endpoint, e);
+
+                if policy.max_attempts.map(|m| attempts >= m).unwrap_or(false) {
+                    return Err(e);
+                }
+                delay = (delay * 2).min(policy.max_backoff);
+            }
+        }
+    }
+}
+
+// sets the nice value of the calling thread, which linux (unlike posix) allows per-thread
+#[cfg(target_os = "linux")]
+fn set_thread_priority(nice: i32) {
+    // a negative nice value usually needs root, or CAP_SYS_NICE
+    let result = unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice) };
+    if result != 0 {
+        warn!("Failed to set worker thread priority to {}: {}", nice, io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_thread_priority(nice: i32) {
+    warn!("Setting the worker thread priority (to {}) is only supported on linux", nice);
+}
+
+impl Worker {
+    // tees <data> into the capture file, if recording.
+    // a failed write stops the recording, rather than the worker.
+    fn record(&self, timestamp: u128, data: &[u8]) {
+        let mut recorder = self.recorder.lock().expect("recorder lock poisoned");
+        if let Some(r) = recorder.as_mut() {
+            if let Err(e) = r.write_chunk(timestamp, data) {
+                error!("Failed to write to capture file, recording stopped: {}", e);
+                *recorder = None;
+            }
+        }
+    }
+
+    fn update_stats(&self, decoder: &FrameDecoder) {
+        *self.stats.lock().expect("stats lock poisoned") = decoder.stats().clone();
+    }
+}
+
+// continuously reads and decodes frames, until the port fails,
+// or until we're asked to stop (or nobody is listening any more), which returns Ok.
+fn read_until_error<T: Transport + ?Sized>(
+    serial: &mut T,
+    decoder: &mut FrameDecoder,
+    worker: &Worker,
+) -> io::Result<()> {
+    let mut serial_temp_buf: Vec<u8> = vec![0; worker.read_buffer_size];
+
+    while !worker.stop.load(Ordering::SeqCst) {
+        match serial.read(serial_temp_buf.as_mut_slice()) {
+            // a serial port never reaches the end, unless it has gone away (a replay just ends)
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(t) => {
+                let data = &serial_temp_buf[..t];
+                let timestamp = serial.timestamp();
+                worker.record(timestamp, data);
+
+                for frame in decoder.push_at(data, timestamp) {
+                    if worker.tx.send(frame.into()).is_err() {
+                        // the receiver has been dropped
+                        return Ok(());
+                    }
+                }
+                worker.update_stats(decoder);
+            }
+            // ignore timeout, other than letting the rates fall
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                decoder.update_rates(serial.timestamp());
+                worker.update_stats(decoder);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+// Where a lidar is mounted on the robot, so that its points come out in the robot's frame
+// (and the points of several lidars end up in the same frame).
+//
+// Measurement angles are turned into points the same way as Measurement::point, with 0 degrees
+// along +y and angles going clockwise towards +x, then moved by the pose:
+//   1. angle_offset is added to the angle, e.g. to correct for the lidar being turned in its mount
+//   2. the angle is negated if mirrored, for a lidar mounted upside down
+//   3. the point is rotated anticlockwise by yaw, then moved by (x, y)
+use crate::protocol::Measurement;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct SensorPose {
+    pub x: f32, // metres, in the robot frame
+    pub y: f32,
+    pub yaw: f32, // degrees, anticlockwise from the robot's x axis
+    pub mirrored: bool, // the lidar's angles go anticlockwise rather than clockwise
+    pub angle_offset: f32, // degrees, added to every measurement angle
+}
+
+impl SensorPose {
+    pub fn new(x: f32, y: f32, yaw: f32) -> Self {
+        SensorPose { x, y, yaw, ..Default::default() }
+    }
+
+    pub fn mirrored(mut self, mirrored: bool) -> Self {
+        self.mirrored = mirrored;
+        self
+    }
+
+    pub fn angle_offset(mut self, offset: f32) -> Self {
+        self.angle_offset = offset;
+        self
+    }
+
+    // the measurement as a point in the robot frame, in metres
+    pub fn point(&self, m: &Measurement) -> (f32, f32) {
+        let mut angle = m.angle + self.angle_offset;
+        if self.mirrored {
+            angle = -angle;
+        }
+        let (sin, cos) = angle.to_radians().sin_cos();
+        self.transform((m.distance_mm * sin / 1000.0, m.distance_mm * cos / 1000.0))
+    }
+
+    // moves a point (in metres) from the lidar's frame into the robot's frame
+    pub fn transform(&self, point: (f32, f32)) -> (f32, f32) {
+        let (sin, cos) = self.yaw.to_radians().sin_cos();
+        let (x, y) = point;
+        (self.x + x * cos - y * sin, self.y + x * sin + y * cos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1 metre away at <angle>
+    fn point(pose: SensorPose, angle: f32) -> (f32, f32) {
+        pose.point(&Measurement { angle, signal_quality: 0, distance_mm: 1000.0 })
+    }
+
+    fn assert_near(actual: (f32, f32), expected: (f32, f32)) {
+        assert!((actual.0 - expected.0).abs() < 1e-5 && (actual.1 - expected.1).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn default_pose_is_the_lidar_frame() {
+        let m = Measurement { angle: 30.0, signal_quality: 0, distance_mm: 2000.0 };
+        assert_near(SensorPose::default().point(&m), m.point());
+        // 0 degrees along +y, clockwise towards +x
+        assert_near(point(SensorPose::default(), 0.0), (0.0, 1.0));
+        assert_near(point(SensorPose::default(), 90.0), (1.0, 0.0));
+    }
+
+    #[test]
+    fn translation() {
+        assert_near(point(SensorPose::new(0.5, -0.25, 0.0), 90.0), (1.5, -0.25));
+    }
+
+    #[test]
+    fn yaw_rotates_anticlockwise() {
+        // +y turned 90 degrees anticlockwise is -x
+        assert_near(point(SensorPose::new(0.0, 0.0, 90.0), 0.0), (-1.0, 0.0));
+        // rotated before being moved
+        assert_near(point(SensorPose::new(1.0, 2.0, 90.0), 90.0), (1.0, 3.0));
+    }
+
+    #[test]
+    fn mirrored_angles_go_anticlockwise() {
+        assert_near(point(SensorPose::default().mirrored(true), 90.0), (-1.0, 0.0));
+        assert_near(point(SensorPose::default().mirrored(true), 0.0), (0.0, 1.0));
+    }
+
+    #[test]
+    fn angle_offset_is_added_before_mirroring() {
+        assert_near(point(SensorPose::default().angle_offset(90.0), 0.0), (1.0, 0.0));
+        assert_near(point(SensorPose::default().angle_offset(90.0).mirrored(true), 0.0), (-1.0, 0.0));
+    }
+}
@@ -8,7 +8,7 @@
 
 // Specific design goals for rust port
 // - improve 'glitched' data decoding, by having multiple 'partial frames' all being assembled simultaneously.
-// - ideally, be no_std compatible, so that this state machine / protocol structs can be used on microcontrollers too.
+// - be no_std compatible, so that the decoder can be used on microcontrollers too (see frame.rs).
 // - be serializable to CSV / JSON for data capture
 
 // DATA FRAME STRUCTURE (DELTA-2)
@@ -20,10 +20,18 @@ use itertools::Itertools;
 // the state machine code much cleaner! (no idea tho).
 // offset, length, expected constnat value
 use log::debug;
-use std::{io::{Error, Write}, fmt::Display};
+use crate::csv::CsvWriter;
+use crate::error::Error;
+use crate::frame::{FrameParser, RawFrame};
+use crate::pose::SensorPose;
+use std::{io::{self, Write}, fmt::Display};
 use serde::Serialize;
+use std::collections::VecDeque;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+// the parts of the protocol which don't need std live in frame.rs
+pub use crate::frame::{checksum, header_byte_valid, DecoderStats, Measurement, ZERO_ANGLE_OFFSET};
+
 // These are the hard-coded 'magic numbers' which we expect to receive in each frame.
 // const FRAME_HEADER : FramePart = FramePart{ offset: 0, length: 1, expected: Some(0xAA) };
 // const FRAME_LENGTH : FramePart = FramePart{ offset: 1, length: 2, expected: None };
@@ -46,20 +54,6 @@ pub struct PartialFrame {
     pub timestamp: u128, // unix epoch nanoseconds when the header was identified.
 }
 
-#[derive(Serialize)]
-#[derive(Debug, Clone)]
-pub struct Measurement {
-    pub angle : f32, // degrees
-    pub signal_quality: u8,
-    pub distance_mm : f32,
-}
-
-impl Display for Measurement {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!("{:3.1} deg - {:3.1} cm",self.angle,self.distance_mm/10.0))
-    }
-}
-
 #[derive(Debug, Clone, Serialize)]
 pub struct MeasurementFrame {
     pub rpm: f32,
@@ -67,34 +61,39 @@ pub struct MeasurementFrame {
     pub start_angle: f32,
     pub timestamp: u128, // unix epoch nanoseconds when the header was identified.
     pub measurements: Vec<Measurement>,
-}
-
-impl Default for Measurement {
-    fn default() -> Self {
-        Measurement {
-            angle: 0.0,
-            signal_quality: 0,
-            distance_mm: 0.0,
-        }
-    }
+    // where the lidar is mounted, used by points(). set by Lidar::set_pose.
+    // left out of the JSON, which has always been the frame as the lidar sent it
+    #[serde(skip)]
+    pub pose: SensorPose,
 }
 
 impl Measurement {
     pub fn point(&self) -> (f32,f32) {
-        // returns the data in cartesian metre units
+        // returns the data in cartesian metre units, in the lidar's own frame
         let dx = self.distance_mm * self.angle.to_radians().sin() / 1000.0;
         let dy = self.distance_mm * self.angle.to_radians().cos() / 1000.0;
         (dx,dy)
     }
+
+    // like point, but in the robot's frame, for a lidar mounted at <pose>
+    pub fn point_in(&self, pose: &SensorPose) -> (f32,f32) {
+        pose.point(self)
+    }
 }
 
 
-// This FullScan struct is built from a vector of MeasurementFrames,
-// and is 'completed' when the full angle-range is satisfied.
+// The lidar sends one revolution as 15 measurement frames, each covering a 24 degree sector.
+pub const SECTORS_PER_SCAN: usize = 15;
+pub const SECTOR_ANGLE: f32 = 360.0 / (SECTORS_PER_SCAN as f32);
+// most bins FullScan::to_range_array will make, far finer than the lidar's resolution
+pub const MAX_RANGE_BINS: usize = 1 << 20;
+
+// This FullScan struct is built from a vector of MeasurementFrames, one per sector,
+// and is 'completed' when every sector of the revolution is present.
 // Other information such as min/max timestamp can be used to validate the
 // correctness of the data.
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct FullScan {
     pub frames: Vec<MeasurementFrame>,
 }
@@ -104,9 +103,16 @@ impl FullScan {
         serde_json::to_string(&self).expect("Serialized to JSON")
     }
 
+    // the measurements as CSV, with a header row, see csv.rs
+    pub fn to_csv(&self) -> String {
+        let mut writer = CsvWriter::new(vec![]).expect("Written to a Vec");
+        writer.write_scan(self).expect("Written to a Vec");
+        String::from_utf8(writer.into_inner()).expect("CSV is UTF-8")
+    }
+
     pub fn points(&self) -> Vec<(f32,f32)> {
-        // calls .cartesian on all measurements, returning a 'point cloud'
-        self.frames.iter().map(|f| f.measurements.iter().map(|m| m.point()).collect_vec()).flatten().collect_vec()
+        // calls .cartesian on all measurements, returning a 'point cloud' in the robot frame (see MeasurementFrame::pose)
+        self.frames.iter().flat_map(|f| f.points()).collect_vec()
     }
 
     pub fn rpm(&self) -> f32 {
@@ -125,17 +131,199 @@ impl FullScan {
         (max as i64) - (min as i64)
     }
 
+    pub fn has_sector(&self, sector: usize) -> bool {
+        self.frames.iter().any(|f| f.sector() == sector)
+    }
+
+    // sectors (0 to 14, see MeasurementFrame::sector) which no frame has been received for
+    pub fn missing_sectors(&self) -> Vec<usize> {
+        (0..SECTORS_PER_SCAN).filter(|s| !self.has_sector(*s)).collect_vec()
+    }
+
     pub fn complete(&self) -> bool {
-        // sort the angles of the frames, then check if it integrates to a circle?
-        let angles : f32 = self.frames.iter().map(|f| f.sector_angle()).sum();
-        // get delta between sequential angles
-        angles > 720.0
+        self.missing_sectors().is_empty()
+    }
+
+    // bins the measurements into evenly spaced angles from <angle_min> to <angle_max> (inclusive, in degrees),
+    // <increment> degrees apart. a range wider than 360 degrees is cut down to one full circle.
+    pub fn to_range_array(&self, angle_min: f32, angle_max: f32, increment: f32, policy: BinPolicy) -> Result<RangeArray, Error> {
+        if !angle_min.is_finite() || !angle_max.is_finite() || angle_max < angle_min {
+            return Err(Error::InvalidParameter(format!("invalid angle range {} to {}", angle_min, angle_max)));
+        }
+        if !increment.is_finite() || increment <= 0.0 {
+            return Err(Error::InvalidParameter(format!("invalid angle increment {}", increment)));
+        }
+        if 360.0 / increment > MAX_RANGE_BINS as f32 {
+            return Err(Error::InvalidParameter(format!("angle increment {} would need more than {} bins", increment, MAX_RANGE_BINS)));
+        }
+
+        let full_circle = ((360.0 / increment).round() as usize).max(1);
+        let mut count = ((angle_max - angle_min) / increment + 1e-3).floor() as usize + 1;
+        let wraps = count >= full_circle;
+        if wraps {
+            count = full_circle;
+        }
+
+        // per bin: number of valid measurements, range, intensity, angle error of the chosen measurement, and
+        // whether a no-return measurement was seen
+        let mut valid = vec![0u32; count];
+        let mut ranges = vec![0.0f32; count];
+        let mut intensities = vec![0.0f32; count];
+        let mut errors = vec![0.0f32; count];
+        let mut no_return = vec![false; count];
+
+        for m in self.frames.iter().flat_map(|f| f.measurements.iter()) {
+            let d = (m.angle - angle_min).rem_euclid(360.0);
+            let mut i = (d / increment).round() as usize;
+            if wraps {
+                i %= count;
+            } else if i >= count {
+                // just below angle_min still belongs to the first bin
+                if 360.0 - d <= increment / 2.0 {
+                    i = 0;
+                } else {
+                    continue;
+                }
+            }
+
+            if m.distance_mm <= 0.0 {
+                no_return[i] = true;
+                continue;
+            }
+
+            let range = m.distance_mm / 1000.0;
+            let intensity = m.signal_quality as f32;
+            let e = (d - (i as f32) * increment).rem_euclid(360.0);
+            let error = e.min(360.0 - e);
+
+            let replace = match policy {
+                BinPolicy::Nearest => valid[i] == 0 || error < errors[i],
+                BinPolicy::Min => valid[i] == 0 || range < ranges[i],
+                BinPolicy::Mean => {
+                    ranges[i] += range;
+                    intensities[i] += intensity;
+                    false
+                }
+            };
+            if replace {
+                ranges[i] = range;
+                intensities[i] = intensity;
+                errors[i] = error;
+            }
+            valid[i] += 1;
+        }
+
+        for i in 0..count {
+            if valid[i] > 0 {
+                if policy == BinPolicy::Mean {
+                    ranges[i] /= valid[i] as f32;
+                    intensities[i] /= valid[i] as f32;
+                }
+            } else if no_return[i] {
+                ranges[i] = f32::INFINITY;
+                intensities[i] = 0.0;
+            } else {
+                ranges[i] = f32::NAN;
+                intensities[i] = f32::NAN;
+            }
+        }
+
+        Ok(RangeArray {
+            angle_min,
+            angle_max: angle_min + ((count - 1) as f32) * increment,
+            angle_increment: increment,
+            timestamp: self.timestamp(),
+            ranges,
+            intensities,
+        })
     }
 }
 
-impl Default for FullScan {
-    fn default() -> Self {
-        Self { frames: Default::default() }
+// How FullScan::to_range_array picks a range when more than one measurement lands in the same bin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BinPolicy {
+    #[default]
+    Nearest, // the measurement closest in angle to the bin centre
+    Min, // the shortest range
+    Mean, // the average range (and intensity)
+}
+
+// A FullScan resampled onto evenly spaced angles, like a ROS LaserScan message.
+// Bin i is centred on angle_min + i * angle_increment degrees, and ranges are in metres.
+// Bins with no measurements are NaN, and bins where the lidar got no return (zero distance) are infinity.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RangeArray {
+    pub angle_min: f32,
+    pub angle_max: f32, // centre of the last bin
+    pub angle_increment: f32,
+    pub timestamp: u128,
+    pub ranges: Vec<f32>,
+    pub intensities: Vec<f32>, // signal quality, NaN for empty bins and 0 for bins with no return
+}
+
+impl RangeArray {
+    pub fn as_json(&self) -> String {
+        serde_json::to_string(&self).expect("Serialized to JSON")
+    }
+}
+
+impl Display for RangeArray {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("{} bins, {:3.1} to {:3.1} deg", self.ranges.len(), self.angle_min, self.angle_max))
+    }
+}
+
+// Groups MeasurementFrames into FullScans, one per physical revolution.
+// A revolution ends when start_angle wraps around back past 0 degrees, or when a sector we
+// already have turns up again (meaning the frames around the wraparound were lost).
+// The same sector arriving twice in a row replaces the earlier copy, so sectors are never doubled up.
+#[derive(Debug, Clone, Default)]
+pub struct ScanAssembler {
+    scan: FullScan,
+    last_sector: Option<usize>,
+    // false until the first wraparound, as the first revolution has usually started part-way through
+    synced: bool,
+}
+
+impl ScanAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds a frame, returning the previous revolution if this frame started a new one.
+    // revolutions with lost frames are still returned, check FullScan::missing_sectors.
+    // the leading partial revolution seen after new()/reset() is thrown away, unless it happens to be complete.
+    pub fn push(&mut self, frame: MeasurementFrame) -> Option<FullScan> {
+        let sector = frame.sector();
+        let mut finished = None;
+
+        if let Some(last) = self.last_sector {
+            if sector < last || (sector != last && self.scan.has_sector(sector)) {
+                let scan = std::mem::take(&mut self.scan);
+                if self.synced || scan.complete() {
+                    finished = Some(scan);
+                }
+                self.synced = true;
+            }
+        }
+
+        match self.scan.frames.iter_mut().find(|f| f.sector() == sector) {
+            Some(existing) => *existing = frame,
+            None => self.scan.frames.push(frame),
+        }
+        self.last_sector = Some(sector);
+
+        finished
+    }
+
+    // the revolution being assembled so far
+    pub fn partial(&self) -> &FullScan {
+        &self.scan
+    }
+
+    // forgets the current revolution, e.g. after a gap in the data
+    pub fn reset(&mut self) {
+        *self = Self::default();
     }
 }
 
@@ -144,9 +332,21 @@ impl MeasurementFrame {
         serde_json::to_string(&self).expect("Serialized to JSON")
     }
 
+    pub fn to_csv(&self) -> String {
+        let mut writer = CsvWriter::new(vec![]).expect("Written to a Vec");
+        writer.write_frame(self).expect("Written to a Vec");
+        String::from_utf8(writer.into_inner()).expect("CSV is UTF-8")
+    }
+
     pub fn points(&self) -> Vec<(f32,f32)> {
-        // calls .cartesian on all measurements, returning a 'point cloud'
-        self.measurements.iter().map(|m| m.point()).collect_vec()
+        // calls .cartesian on all measurements, returning a 'point cloud' in the robot frame
+        self.measurements.iter().map(|m| m.point_in(&self.pose)).collect_vec()
+    }
+
+    // which 24 degree sector of the revolution this frame covers, 0 being the one starting at 0 degrees.
+    // rounded, as the start angle jitters either side of the sector boundary
+    pub fn sector(&self) -> usize {
+        ((self.start_angle / SECTOR_ANGLE).round() as usize) % SECTORS_PER_SCAN
     }
 
     pub fn sector_angle(&self) -> f32 {
@@ -164,6 +364,16 @@ impl MeasurementFrame {
 
         (e - s) % 360.0
     }
+
+    // the frame as the lidar would have sent it. see RawFrame::encode_measurements for what's kept
+    pub fn to_raw(&self) -> Result<RawFrame, Error> {
+        RawFrame::encode_measurements(self.rpm, self.start_angle, &self.measurements, self.timestamp)
+            .ok_or_else(|| Error::InvalidParameter(format!("{} measurements won't fit in one frame", self.measurements.len())))
+    }
+
+    pub fn to_wire_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.to_raw()?.as_bytes().to_vec())
+    }
 }
 
 impl Display for MeasurementFrame {
@@ -186,77 +396,124 @@ impl Default for MeasurementFrame {
             start_angle: 0.0,
             timestamp: 0,
             measurements: vec![Measurement::default()],
+            pose: SensorPose::default(),
+        }
+    }
+}
+
+impl From<&RawFrame> for MeasurementFrame {
+    fn from(value: &RawFrame) -> Self {
+        if !value.is_measurement_type() {
+            return MeasurementFrame::default();
+        }
+        MeasurementFrame {
+            rpm: value.rpm(),
+            // the 'offset angle' field isn't used, see RawFrame::angle_step
+            offset_angle: value.angle_step(),
+            start_angle: value.start_angle(),
+            timestamp: value.timestamp,
+            measurements: value.measurements().collect_vec(),
+            pose: SensorPose::default(),
         }
     }
 }
 
 impl From<PartialFrame> for MeasurementFrame {
     fn from(value: PartialFrame) -> Self {
-        if !value.is_measurement_type() || !value.finished() {
-            MeasurementFrame::default()
+        match value.raw() {
+            Some(raw) => (&raw).into(),
+            None => MeasurementFrame::default(),
+        }
+    }
+}
+
+// The DEVICEHEALTH (0xAE) frame is sent in place of measurement frames whenever the
+// lidar is not able to range, e.g. while the motor is spinning up, or after it has stalled.
+// Payload byte 0 is the motor speed, in the same 0.05 rps units as the measurement frame.
+// Any bytes after that are device error codes, where zero means 'no error'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum HealthStatus {
+    MotorStalled, // motor is not turning at all
+    MotorSpeedLow, // motor is turning, but not fast enough to take measurements
+    Fault(u8), // device reported a non-zero error code
+}
+
+impl Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthStatus::MotorStalled => f.write_str("motor stalled"),
+            HealthStatus::MotorSpeedLow => f.write_str("motor speed too low"),
+            HealthStatus::Fault(code) => f.write_str(&format!("device fault 0x{:02X}", code)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthFrame {
+    pub rpm: f32,
+    pub error_codes: Vec<u8>,
+    pub timestamp: u128, // unix epoch nanoseconds when the header was identified.
+}
+
+impl HealthFrame {
+    pub fn as_json(&self) -> String {
+        serde_json::to_string(&self).expect("Serialized to JSON")
+    }
+
+    pub fn status(&self) -> HealthStatus {
+        // an explicit error code takes priority over the motor speed
+        if let Some(code) = self.error_codes.iter().find(|c| **c != 0) {
+            HealthStatus::Fault(*code)
+        } else if self.rpm == 0.0 {
+            HealthStatus::MotorStalled
         } else {
-            let data_start: usize = 8;
-            let rpm_raw: u8 = value.data.as_slice()[data_start];
-            let rpm : f32 = (rpm_raw as f32) * 3.0;
-
-            // assemble offset angle
-            let off_msb: u8 = value.data.as_slice()[data_start + 1];
-            let off_lsb: u8 = value.data.as_slice()[data_start + 2];
-            let _offset_angle = u16::from_be_bytes([off_msb, off_lsb]);
-
-            // assemble start angle
-            let start_msb: u8 = value.data.as_slice()[data_start + 3];
-            let start_lsb: u8 = value.data.as_slice()[data_start + 4];
-            let start_angle = u16::from_be_bytes([start_msb, start_lsb]);
-            
-            // OLD: Offset angle was read from data.
-            // let offset_angle_deg : f32 = (offset_angle as f32) * 0.01;
-            // NEW: 'Offset angle' is used as the angle step between each measurement.
-            // For the delta 2A, this is 24deg/(num of measurements)
-            // and there are a total of 15 frames for the full 360 degree sweep.
-            let offset_angle_deg : f32 = 24.0 / (value.measurements_count() as f32);
-
-            // 180 degrees means the 0-point is opposite the motor location, rather than on-top of the motor.
-            let start_angle_deg : f32 = ((start_angle as f32) * 0.01 + 180.0) % 360.0;
-
-            let mut m_frame = MeasurementFrame {
-                rpm,
-                offset_angle:offset_angle_deg,
-                start_angle:start_angle_deg,
-                timestamp:value.timestamp,
-                measurements: vec![],
-            };
+            HealthStatus::MotorSpeedLow
+        }
+    }
 
-            // iterate the payload data 3 bytes at a time
-            let mut readings: Vec<Measurement> = value
-                .data
-                .iter()
-                .skip(data_start + 5)
-                .collect_vec()
-                .chunks(3)
-                .take(value.measurements_count().into())
-                .enumerate()
-                .map(|(i,m)| {
-                    // mapping three bytes at a time into a measurement
-                    let signal_quality: u8 = *m[0];
-                    let distance_msb: u8 = *m[1];
-                    let distance_lsb: u8 = *m[2];
-                    let dist_raw = u16::from_be_bytes([distance_msb,distance_lsb]);
-                    let dist_mm = (dist_raw as f32) * 0.25;
+    pub fn motor_stalled(&self) -> bool {
+        self.status() == HealthStatus::MotorStalled
+    }
 
-                    let angle = start_angle_deg + (i as f32) * offset_angle_deg;
+    pub fn motor_speed_low(&self) -> bool {
+        self.status() == HealthStatus::MotorSpeedLow
+    }
 
-                    Measurement {
-                        angle,
-                        signal_quality,
-                        distance_mm:dist_mm,
-                    }
-                })
-                .collect();
+    pub fn to_raw(&self) -> Result<RawFrame, Error> {
+        RawFrame::encode_health(self.rpm, &self.error_codes, self.timestamp)
+            .ok_or_else(|| Error::InvalidParameter(format!("{} error codes won't fit in one frame", self.error_codes.len())))
+    }
 
-            m_frame.measurements.append(&mut readings);
+    pub fn to_wire_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.to_raw()?.as_bytes().to_vec())
+    }
+}
 
-            m_frame
+impl Display for HealthFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format!("{} rpm, {}",self.rpm,self.status()))
+    }
+}
+
+impl From<&RawFrame> for HealthFrame {
+    fn from(value: &RawFrame) -> Self {
+        if !value.is_health_type() {
+            return HealthFrame::default();
+        }
+        HealthFrame {
+            // speed is reported in 0.05 rps, same as the measurement frames
+            rpm: value.rpm(),
+            error_codes: value.payload().iter().skip(1).copied().collect_vec(),
+            timestamp: value.timestamp,
+        }
+    }
+}
+
+impl From<PartialFrame> for HealthFrame {
+    fn from(value: PartialFrame) -> Self {
+        match value.raw() {
+            Some(raw) => (&raw).into(),
+            None => HealthFrame::default(),
         }
     }
 }
@@ -276,6 +533,14 @@ impl PartialFrame {
         self.bytes_wanted == 0 && self.crc_16_valid()
     }
 
+    // the finished frame, in the form the no_std decoder hands out
+    pub fn raw(&self) -> Option<RawFrame> {
+        match self.bytes_wanted {
+            0 => RawFrame::from_bytes(&self.data, self.timestamp),
+            _ => None,
+        }
+    }
+
     pub fn is_measurement_type(&self) -> bool {
         if self.data.len() >= 6 {
             self.data.as_slice()[5] == 0xAD
@@ -321,7 +586,7 @@ impl PartialFrame {
             let crc_expected = u16::from_be_bytes([msb,lsb]);
 
             // sum all bytes excluding the crc
-            let crc_calc : u16 = self.data.iter().map(|x| *x as u16).take(end-2).sum();
+            let crc_calc : u16 = checksum(&self.data.as_slice()[..end-2]);
 
             // info!("{:?}",&self.data.as_slice());
             // info!("{},{},{}",end,msb,lsb);
@@ -361,16 +626,16 @@ impl Default for PartialFrame {
 }
 
 impl Write for PartialFrame {
-    fn write_all(&mut self, mut buf: &[u8]) -> std::io::Result<()> {
+    fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
         // clear bytes written counter
         self.bytes_written = 0;
 
         // returns early-Ok when frame is full
         while !buf.is_empty() {
             match self.write(buf) {
-                Ok(0) => return Err(Error::from_raw_os_error(105)),
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, Error::FrameFull)),
                 Ok(n) => buf = &buf[n..],
-                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
                 Err(e) => return Err(e),
             }
         }
@@ -384,7 +649,7 @@ impl Write for PartialFrame {
         self
     }
 
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let mut bytes_eaten: usize = 0;
 
         let bytes_available = buf.len();
@@ -397,60 +662,22 @@ impl Write for PartialFrame {
                 break;
             }
 
-            let accept_byte = match (self.data.len(), d) {
-                (0, 0xAA) => {
-                    // update timestamp on header detect
-                    self.timestamp = get_nanos();
-                    // header
-                    true
-                }
-                (1, _) => {
-                    // DLC
-                    true
-                }
-                (2, _) => {
-                    // DLC
-                    true
-                }
-                (3, 0x01) => {
-                    // type
-                    true
-                }
-                (4, 0x61) => {
-                    // protocol
-                    true
-                }
-                (5, 0xAD | 0xAE) => {
-                    // command type
-                    true
-                }
-                (6, _) => {
-                    // payload len
-                    true
-                }
-                (7, _) => {
-                    // payload len
-                    true
-                }
-                (d, _) => {
-                    d >= 8 
-                }
-            };
+            if self.data.is_empty() && d == 0xAA {
+                // update timestamp on header detect
+                self.timestamp = get_nanos();
+            }
+
+            let accept_byte = header_byte_valid(self.data.len(), d);
 
             if accept_byte {
                 // debug!("accept_byte");
                 self.data.append(&mut vec![d]);
                 self.bytes_wanted -= 1;
 
-                // now do a match to calculate payload length
-                match self.has_payload_length() {
-                    true => {
-                        if self.data.len() == 8 {
-                            // payload len plus 2 for CRC
-                            self.bytes_wanted = self.payload_length() + 2;
-                        }
-                    }
-                    false => (),
+                // now calculate payload length
+                if self.has_payload_length() && self.data.len() == 8 {
+                    // payload len plus 2 for CRC
+                    self.bytes_wanted = self.payload_length() + 2;
                 }
             } else {
                 // didn't accept this byte - but we also aren't finished.
@@ -466,13 +693,309 @@ impl Write for PartialFrame {
         Ok(bytes_eaten)
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
+    fn flush(&mut self) -> io::Result<()> {
         // todo!()
         Ok(())
     }
 }
 
+// Streaming decoder, which turns an arbitrary stream of bytes into complete, CRC-checked frames.
+// Bytes can be pushed in any chunk size (one byte at a time, whole serial reads, whole files),
+// so the decoder doesn't care where they come from.
+//
+// let mut decoder = FrameDecoder::new();
+// for frame in decoder.push(&bytes) { ... }
+//
+// The decoding itself is done by the fixed size FrameParser (see frame.rs for how it recovers from
+// corrupted data). This holds on to whatever doesn't fit into it yet, and hands out PartialFrames.
+#[derive(Default)]
+pub struct FrameDecoder {
+    parser: Box<FrameParser>,
+    pending: VecDeque<(Vec<u8>, u128)>, // (bytes, timestamp) of pushed chunks which haven't been given to the parser yet
+}
+
+impl DecoderStats {
+    pub fn as_json(&self) -> String {
+        serde_json::to_string(&self).expect("Serialized to JSON")
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        FrameDecoder::default()
+    }
+
+    // adds bytes to the decoder, timestamped now, returning an iterator over any completed frames.
+    pub fn push(&mut self, data: &[u8]) -> Frames<'_> {
+        self.push_at(data, get_nanos())
+    }
+
+    // like push, but with a caller-supplied timestamp (e.g. when replaying recorded data).
+    pub fn push_at(&mut self, data: &[u8], timestamp: u128) -> Frames<'_> {
+        if !data.is_empty() {
+            self.pending.push_back((data.to_vec(), timestamp));
+            self.fill();
+        }
+        self.update_rates(timestamp);
+        Frames { decoder: self }
+    }
+
+    pub fn stats(&self) -> &DecoderStats {
+        self.parser.stats()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.parser.reset_stats();
+    }
+
+    // number of bytes waiting to be decoded
+    pub fn buffered(&self) -> usize {
+        self.parser.buffered() + self.pending.iter().map(|(d, _)| d.len()).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.fill();
+        while !self.pending.is_empty() {
+            self.parser.clear();
+            self.fill();
+        }
+        self.parser.clear();
+    }
+
+    // decodes the next complete frame out of the buffered bytes, if there is one.
+    pub fn next_frame(&mut self) -> Option<PartialFrame> {
+        loop {
+            if let Some(raw) = self.parser.next_frame() {
+                return Some(PartialFrame {
+                    data: raw.as_bytes().to_vec(),
+                    bytes_wanted: 0,
+                    bytes_written: raw.as_bytes().len(),
+                    timestamp: raw.timestamp,
+                });
+            }
+            // the parser has made room for more, if there is any
+            if self.pending.is_empty() {
+                return None;
+            }
+            self.fill();
+        }
+    }
+
+    // recalculates the per-second rates, once at least a second has passed since the last time.
+    // called on every push, but can also be called when no data is arriving, so the rates drop to zero.
+    pub fn update_rates(&mut self, timestamp: u128) {
+        self.parser.update_rates(timestamp);
+    }
+
+    // gives the parser as many pending bytes as it has room for
+    fn fill(&mut self) {
+        while let Some((data, timestamp)) = self.pending.front_mut() {
+            let n = self.parser.push(data, *timestamp);
+            if n < data.len() {
+                data.drain(..n);
+                break;
+            }
+            self.pending.pop_front();
+        }
+    }
+}
+
+// Iterator over the frames completed by a FrameDecoder::push
+pub struct Frames<'a> {
+    decoder: &'a mut FrameDecoder,
+}
+
+impl Iterator for Frames<'_> {
+    type Item = PartialFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decoder.next_frame()
+    }
+}
+
 pub fn get_nanos() -> u128 {
     // get the current epoch time in nanoseconds
     SystemTime::now().duration_since(UNIX_EPOCH).expect("Time went backwards").as_nanos()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{HEADER_LENGTH, MAX_MEASUREMENTS};
+
+    fn measurements(count: usize) -> Vec<Measurement> {
+        (0..count).map(|i| Measurement { angle: 0.0, signal_quality: (i * 7) as u8, distance_mm: 1000.0 + (i as f32) * 12.3 }).collect()
+    }
+
+    // encodes <frame>, and decodes it again
+    fn round_trip(frame: &MeasurementFrame) -> MeasurementFrame {
+        let mut decoder = FrameDecoder::new();
+        let mut frames = decoder.push_at(&frame.to_wire_bytes().unwrap(), frame.timestamp).map(MeasurementFrame::from).collect_vec();
+        assert_eq!(frames.len(), 1);
+        frames.remove(0)
+    }
+
+    #[test]
+    fn measurement_frames_survive_a_round_trip() {
+        let frame = MeasurementFrame { rpm: 301.0, start_angle: 48.0, timestamp: 1234, measurements: measurements(28), ..MeasurementFrame::default() };
+        let decoded = round_trip(&frame);
+
+        // sent in steps of 3 rpm, and 0.25 mm
+        assert_eq!(decoded.rpm, 300.0);
+        assert!((decoded.start_angle - 48.0).abs() < 0.01);
+        assert_eq!(decoded.timestamp, 1234);
+        assert_eq!(decoded.measurements.len(), 28);
+        for (sent, received) in frame.measurements.iter().zip(decoded.measurements.iter()) {
+            assert_eq!(received.signal_quality, sent.signal_quality);
+            assert_eq!(received.distance_mm, (sent.distance_mm * 4.0).round() / 4.0);
+        }
+    }
+
+    #[test]
+    fn start_angle_wraps_at_360_degrees() {
+        // 359.999 degrees from the zero offset rounds up to 360, which is sent as 0
+        let frame = MeasurementFrame { start_angle: ZERO_ANGLE_OFFSET - 0.001, measurements: measurements(1), ..MeasurementFrame::default() };
+        let bytes = frame.to_wire_bytes().unwrap();
+        assert_eq!(&bytes[HEADER_LENGTH + 3..HEADER_LENGTH + 5], &[0, 0]);
+        assert_eq!(round_trip(&frame).start_angle, ZERO_ANGLE_OFFSET);
+
+        let frame = MeasurementFrame { start_angle: ZERO_ANGLE_OFFSET + 359.99, measurements: measurements(1), ..MeasurementFrame::default() };
+        assert!((round_trip(&frame).start_angle - (ZERO_ANGLE_OFFSET - 0.01)).abs() < 0.001);
+    }
+
+    fn scan_of(measurements: &[(f32, f32, u8)]) -> FullScan {
+        let measurements = measurements.iter().map(|&(angle, distance_mm, signal_quality)| Measurement { angle, distance_mm, signal_quality }).collect();
+        FullScan { frames: vec![MeasurementFrame { measurements, ..MeasurementFrame::default() }] }
+    }
+
+    #[test]
+    fn range_array_bin_policies() {
+        let scan = scan_of(&[(9.8, 1000.0, 100), (10.1, 2000.0, 200)]);
+        let bin = |policy| {
+            let array = scan.to_range_array(0.0, 359.0, 1.0, policy).unwrap();
+            assert_eq!(array.ranges.len(), 360);
+            (array.ranges[10], array.intensities[10])
+        };
+        assert_eq!(bin(BinPolicy::Nearest), (2.0, 200.0));
+        assert_eq!(bin(BinPolicy::Min), (1.0, 100.0));
+        assert_eq!(bin(BinPolicy::Mean), (1.5, 150.0));
+    }
+
+    #[test]
+    fn range_array_empty_and_no_return_bins() {
+        let scan = scan_of(&[(20.0, 0.0, 0), (30.0, 0.0, 0), (30.2, 1500.0, 50)]);
+        let array = scan.to_range_array(0.0, 359.0, 1.0, BinPolicy::Nearest).unwrap();
+        assert_eq!((array.ranges[20], array.intensities[20]), (f32::INFINITY, 0.0));
+        // a return wins over no return
+        assert_eq!((array.ranges[30], array.intensities[30]), (1.5, 50.0));
+        assert!(array.ranges[40].is_nan() && array.intensities[40].is_nan());
+    }
+
+    #[test]
+    fn range_array_edges() {
+        // just below angle_min belongs to the first bin, further below to none
+        let scan = scan_of(&[(89.7, 1000.0, 100), (89.0, 2000.0, 100), (180.4, 3000.0, 100)]);
+        let array = scan.to_range_array(90.0, 180.0, 1.0, BinPolicy::Nearest).unwrap();
+        assert_eq!(array.ranges.len(), 91);
+        assert_eq!(array.ranges[0], 1.0);
+        assert_eq!(array.ranges[90], 3.0);
+        assert_eq!(array.ranges.iter().filter(|r| !r.is_nan()).count(), 2);
+
+        // more than a full circle is cut down to one, with the last bin wrapping around to the first
+        let scan = scan_of(&[(359.8, 1000.0, 100)]);
+        let array = scan.to_range_array(0.0, 720.0, 1.0, BinPolicy::Nearest).unwrap();
+        assert_eq!(array.ranges.len(), 360);
+        assert_eq!(array.angle_max, 359.0);
+        assert_eq!(array.ranges[0], 1.0);
+    }
+
+    #[test]
+    fn range_array_rejects_invalid_arguments() {
+        let scan = scan_of(&[]);
+        for (min, max, increment) in [
+            (10.0, 0.0, 1.0),
+            (f32::NAN, 10.0, 1.0),
+            (0.0, f32::INFINITY, 1.0),
+            (0.0, 10.0, 0.0),
+            (0.0, 10.0, -1.0),
+            (0.0, 10.0, f32::NAN),
+            (0.0, 10.0, f32::INFINITY),
+            (0.0, 10.0, 1e-30),
+            (0.0, 10.0, 1e-6),
+        ] {
+            let result = scan.to_range_array(min, max, increment, BinPolicy::Nearest);
+            assert!(matches!(result, Err(Error::InvalidParameter(_))), "{} {} {}", min, max, increment);
+        }
+    }
+
+    // a frame of <sector>, with its start angle a little either side of the boundary
+    fn sector_frame(sector: usize, timestamp: u128) -> MeasurementFrame {
+        let jitter = if sector.is_multiple_of(2) { 0.02 } else { -0.02 };
+        let start_angle = ((sector as f32) * SECTOR_ANGLE + jitter).rem_euclid(360.0);
+        MeasurementFrame { start_angle, timestamp, ..MeasurementFrame::default() }
+    }
+
+    // pushes frames of <sectors>, returning the revolutions finished
+    fn assemble(assembler: &mut ScanAssembler, sectors: impl IntoIterator<Item = usize>) -> Vec<FullScan> {
+        sectors.into_iter().enumerate().filter_map(|(i, s)| assembler.push(sector_frame(s, i as u128))).collect()
+    }
+
+    #[test]
+    fn assembler_returns_whole_revolutions() {
+        let mut assembler = ScanAssembler::new();
+        let scans = assemble(&mut assembler, (0..SECTORS_PER_SCAN).chain([0]));
+        assert_eq!(scans.len(), 1);
+        assert!(scans[0].complete());
+        assert_eq!(scans[0].frames.iter().map(|f| f.sector()).collect_vec(), (0..SECTORS_PER_SCAN).collect_vec());
+        assert_eq!(assembler.partial().frames.len(), 1);
+    }
+
+    #[test]
+    fn assembler_replaces_repeated_sectors() {
+        let mut assembler = ScanAssembler::new();
+        let scans = assemble(&mut assembler, (0..=5).chain(5..SECTORS_PER_SCAN).chain([0]));
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].frames.len(), SECTORS_PER_SCAN);
+        // the later copy
+        assert_eq!(scans[0].frames[5].timestamp, 6);
+    }
+
+    #[test]
+    fn assembler_returns_revolutions_with_missing_sectors() {
+        let mut assembler = ScanAssembler::new();
+        // synced by the wraparound after sector 14, as the first revolution would only be kept if it were complete
+        let scans = assemble(&mut assembler, [14].into_iter().chain((0..SECTORS_PER_SCAN).filter(|s| *s != 7)).chain([0]));
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].missing_sectors(), vec![7]);
+    }
+
+    #[test]
+    fn assembler_joins_part_way_through_a_revolution() {
+        let mut assembler = ScanAssembler::new();
+        // the leading partial revolution is thrown away
+        let scans = assemble(&mut assembler, (7..SECTORS_PER_SCAN).chain(0..SECTORS_PER_SCAN).chain(0..3));
+        assert_eq!(scans.len(), 1);
+        assert!(scans[0].complete());
+        assert_eq!(scans[0].frames[0].sector(), 0);
+
+        // and once synced, a partial revolution is still returned
+        let scans = assemble(&mut assembler, (3..10).chain(0..2));
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].missing_sectors(), (10..SECTORS_PER_SCAN).collect_vec());
+    }
+
+    #[test]
+    fn pose_is_left_out_of_the_json() {
+        let frame = MeasurementFrame { pose: SensorPose::new(1.0, 2.0, 90.0), ..MeasurementFrame::default() };
+        assert!(!frame.as_json().contains("pose"));
+    }
+
+    #[test]
+    fn too_many_measurements_are_rejected() {
+        let frame = MeasurementFrame { measurements: measurements(MAX_MEASUREMENTS), ..MeasurementFrame::default() };
+        assert!(frame.to_raw().is_ok());
+
+        let frame = MeasurementFrame { measurements: measurements(MAX_MEASUREMENTS + 1), ..MeasurementFrame::default() };
+        assert!(matches!(frame.to_raw(), Err(Error::InvalidParameter(_))));
+        assert!(matches!(frame.to_wire_bytes(), Err(Error::InvalidParameter(_))));
+    }
+}
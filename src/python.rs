@@ -0,0 +1,1027 @@
+// Python bindings, built into the delta2_lidar_py extension module.
+// pyo3 0.19's macros expand to impl blocks inside a function body,
+// and check pyo3-internal cfgs which newer rustc doesn't know about.
+#![allow(non_local_definitions, unexpected_cfgs)]
+
+use crate::config::{FlowControl, LidarConfig, Parity};
+use crate::csv::{CsvReader, CsvWriter};
+use crate::discover::{self, DiscoveredPort, DiscoveryOptions};
+use crate::error::Error;
+use crate::group::{GroupScan, LidarGroup};
+use crate::lidar::{Lidar, LidarEvent, ReconnectPolicy};
+use crate::pose::SensorPose;
+use crate::protocol::{MeasurementFrame,Measurement, FullScan, HealthFrame, DecoderStats, RangeArray, BinPolicy};
+use crate::queue::OverflowPolicy;
+
+use log::warn;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyOSError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyModule, PyTuple};
+use pyo3::PyResult;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::time::{Duration, Instant};
+
+// Python exception hierarchy, one class per Error variant that callers are likely to handle.
+// LidarError derives from OSError, so existing `except OSError` handlers keep working.
+create_exception!(delta2_lidar_py, LidarError, PyOSError);
+create_exception!(delta2_lidar_py, SerialOpenError, LidarError);
+create_exception!(delta2_lidar_py, DisconnectedError, LidarError);
+create_exception!(delta2_lidar_py, HealthFaultError, LidarError);
+create_exception!(delta2_lidar_py, ReadTimeoutError, LidarError);
+create_exception!(delta2_lidar_py, WorkerPanickedError, LidarError);
+
+impl From<Error> for PyErr {
+    fn from(value: Error) -> Self {
+        let msg = value.to_string();
+        match value {
+            Error::SerialOpen(_) => SerialOpenError::new_err(msg),
+            Error::NotOpened | Error::Disconnected => DisconnectedError::new_err(msg),
+            // the health frame is passed along as the second exception argument
+            Error::HealthFault(h) => HealthFaultError::new_err((msg, PyHealthFrame { frame: h })),
+            Error::Timeout => ReadTimeoutError::new_err(msg),
+            Error::WorkerPanicked => WorkerPanickedError::new_err(msg),
+            Error::InvalidParameter(_) => PyValueError::new_err(msg),
+            Error::Io(_) | Error::FrameFull => LidarError::new_err(msg),
+        }
+    }
+}
+
+
+// Owns a float32 buffer built on the Rust side, and describes it to numpy through __array_interface__,
+// so numpy.asarray() can wrap the memory without copying it (the buffer protocol isn't available
+// with the abi3 limited API). The resulting array keeps this object alive, and is read-only.
+#[pyclass]
+#[pyo3{name = "ArrayBuffer"}]
+struct PyArrayBuffer {
+    data: Vec<f32>,
+    shape: Vec<usize>,
+}
+
+#[pymethods]
+impl PyArrayBuffer {
+    #[getter]
+    fn __array_interface__(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let typestr = if cfg!(target_endian = "little") { "<f4" } else { ">f4" };
+        let interface = PyDict::new(py);
+        interface.set_item("shape", PyTuple::new(py, &self.shape))?;
+        interface.set_item("typestr", typestr)?;
+        interface.set_item("data", (self.data.as_ptr() as usize, true))?;
+        interface.set_item("version", 3)?;
+        Ok(interface.into())
+    }
+}
+
+// hands <data> over to numpy as an array of <shape>, without copying.
+fn numpy_array(py: Python<'_>, data: Vec<f32>, shape: Vec<usize>) -> PyResult<PyObject> {
+    debug_assert_eq!(data.len(), shape.iter().product::<usize>());
+    let np = py.import("numpy")?;
+    let buffer = Py::new(py, PyArrayBuffer { data, shape })?;
+    Ok(np.call_method1("asarray", (buffer,))?.into())
+}
+
+// N x 2 array of (x, y) points in metres, in each frame's robot frame, or N x 3 with a zero z column.
+// written straight into the one buffer numpy ends up owning
+fn points_array<'a>(py: Python<'_>, frames: impl Iterator<Item = &'a MeasurementFrame> + Clone, z: bool) -> PyResult<PyObject> {
+    let columns = if z { 3 } else { 2 };
+    let rows = frames.clone().map(|f| f.measurements.len()).sum::<usize>();
+    let mut data = Vec::with_capacity(rows * columns);
+    for frame in frames {
+        for m in &frame.measurements {
+            let (x, y) = m.point_in(&frame.pose);
+            data.extend_from_slice(&[x, y, 0.0][..columns]);
+        }
+    }
+    numpy_array(py, data, vec![rows, columns])
+}
+
+// N x 3 array of (angle in degrees, distance in mm, signal quality)
+fn measurement_array<'a>(py: Python<'_>, measurements: impl Iterator<Item = &'a Measurement>) -> PyResult<PyObject> {
+    let data = measurements.flat_map(|m| [m.angle, m.distance_mm, m.signal_quality as f32]).collect::<Vec<f32>>();
+    let rows = data.len() / 3;
+    numpy_array(py, data, vec![rows, 3])
+}
+
+// wraps an event in the matching python class
+fn event_object(py: Python<'_>, event: LidarEvent) -> PyObject {
+    match event {
+        LidarEvent::Measurement(msg) => PyMeasurementFrame { frame: msg }.into_py(py),
+        LidarEvent::Health(msg) => PyHealthFrame { frame: msg }.into_py(py),
+        LidarEvent::Disconnected => PyConnectionEvent { connected: false }.into_py(py),
+        LidarEvent::Reconnected => PyConnectionEvent { connected: true }.into_py(py),
+    }
+}
+
+// converts a python float of seconds into a Duration
+fn seconds(s: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(s).map_err(|e| PyValueError::new_err(format!("invalid duration {}: {}", s, e)))
+}
+
+// the result of closing in __exit__. if the with block is already raising an exception, an error from closing
+// is only logged, so that it doesn't replace the original exception (and its traceback)
+fn close_on_exit(result: Result<(), Error>, exc_type: Option<&PyAny>) -> PyResult<()> {
+    match (result, exc_type) {
+        (Err(e), Some(_)) => {
+            warn!("Error while closing, after an exception in the with block: {}", e);
+            Ok(())
+        }
+        (result, _) => Ok(result?),
+    }
+}
+
+// how long to wait with the GIL released, before checking for Ctrl-C (KeyboardInterrupt)
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// calls recv with the GIL released, in short slices so that signals are still handled.
+// a timeout of None waits forever.
+fn recv_interruptible<D: Send, T: Send>(
+    py: Python<'_>,
+    dev: &mut D,
+    timeout: Option<f64>,
+    recv: fn(&mut D, Duration) -> Result<T, Error>,
+) -> PyResult<T> {
+    let deadline = match timeout {
+        // a timeout too long to represent is the same as none
+        Some(t) => Instant::now().checked_add(seconds(t)?),
+        None => None,
+    };
+
+    loop {
+        let slice = match deadline {
+            Some(d) => d.saturating_duration_since(Instant::now()).min(SIGNAL_CHECK_INTERVAL),
+            None => SIGNAL_CHECK_INTERVAL,
+        };
+
+        match py.allow_threads(|| recv(dev, slice)) {
+            Err(Error::Timeout) => {
+                if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                    return Err(Error::Timeout.into());
+                }
+                // raises KeyboardInterrupt etc.
+                py.check_signals()?;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+#[pyclass]
+#[pyo3{name = "Lidar"}]
+struct PyLidar {
+    dev: Lidar,
+}
+
+#[pyclass]
+#[pyo3{name = "MeasurementFrame"}]
+#[derive(Clone)]
+struct PyMeasurementFrame {
+    frame: MeasurementFrame,
+}
+
+#[pyclass]
+#[pyo3{name = "Measurement"}]
+#[derive(Clone)]
+struct PyMeasurement{
+    m: Measurement,
+    pose: SensorPose, // of the frame it came from
+}
+
+#[pyclass]
+#[pyo3{name = "HealthFrame"}]
+#[derive(Clone)]
+struct PyHealthFrame {
+    frame: HealthFrame,
+}
+
+#[pyclass]
+#[pyo3{name = "ConnectionEvent"}]
+#[derive(Clone)]
+struct PyConnectionEvent {
+    connected: bool,
+}
+
+#[pyclass]
+#[pyo3{name = "DecoderStats"}]
+#[derive(Clone)]
+struct PyDecoderStats {
+    stats: DecoderStats,
+}
+
+#[pyclass]
+#[pyo3{name = "FullScan"}]
+#[derive(Clone)]
+struct PyFullScan {
+    scan: FullScan,
+}
+
+#[pyclass]
+#[pyo3{name = "RangeArray"}]
+#[derive(Clone)]
+struct PyRangeArray {
+    ranges: RangeArray,
+}
+
+#[pyclass]
+#[pyo3{name = "LidarGroup"}]
+struct PyLidarGroup {
+    group: LidarGroup,
+}
+
+#[pyclass]
+#[pyo3{name = "GroupScan"}]
+#[derive(Clone)]
+struct PyGroupScan {
+    scan: GroupScan,
+}
+
+#[pyclass]
+#[pyo3{name = "DiscoveredPort"}]
+#[derive(Clone)]
+struct PyDiscoveredPort {
+    port: DiscoveredPort,
+}
+
+#[pymethods]
+impl PyLidar {
+    #[new]
+    #[pyo3(signature = (port=None))]
+    fn new(port: Option<String>) -> PyResult<Self> {
+        let mut bus = Lidar::new();
+        if let Some(p) = port {
+            bus.open(p)?;
+        }
+        Ok(PyLidar{dev: bus})
+    }
+
+    fn close(&mut self) -> PyResult<()> {
+        self.dev.close()?;
+        Ok(())
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, exc_type: Option<&PyAny>, _exc_value: Option<&PyAny>, _traceback: Option<&PyAny>) -> PyResult<bool> {
+        close_on_exit(self.dev.close(), exc_type)?;
+        // don't suppress any exception raised inside the with block
+        Ok(false)
+    }
+
+    // enables reconnecting after the port fails, or disables it with enabled=False.
+    // takes effect on the next call to open().
+    #[pyo3(signature = (enabled=true, retry_interval=0.5, max_backoff=10.0, max_attempts=None))]
+    fn set_reconnect_policy(&mut self, enabled: bool, retry_interval: f64, max_backoff: f64, max_attempts: Option<u32>) -> PyResult<()> {
+        let policy = ReconnectPolicy {
+            retry_interval: seconds(retry_interval)?,
+            max_backoff: seconds(max_backoff)?,
+            max_attempts,
+        };
+        self.dev.set_reconnect_policy(if enabled { Some(policy) } else { None });
+        Ok(())
+    }
+
+    // bounds the frame queue to <capacity> frames (None is unbounded), with <policy> one of
+    // "drop_oldest", "drop_newest" or "block". takes effect on the next call to open().
+    #[pyo3(signature = (capacity=None, policy="drop_oldest"))]
+    fn set_queue(&mut self, capacity: Option<usize>, policy: &str) -> PyResult<()> {
+        let policy = match policy {
+            "drop_oldest" => OverflowPolicy::DropOldest,
+            "drop_newest" => OverflowPolicy::DropNewest,
+            "block" => OverflowPolicy::Block,
+            p => return Err(PyValueError::new_err(format!("unknown overflow policy '{}'", p))),
+        };
+        self.dev.set_queue(capacity, policy);
+        Ok(())
+    }
+
+    // sets where the lidar is mounted on the robot, so that points come out in the robot's frame:
+    // (x, y) in metres, yaw in degrees anticlockwise, mirrored for a lidar mounted upside down,
+    // and angle_offset degrees added to every measurement angle first. applies to frames received from now on.
+    #[pyo3(signature = (x=0.0, y=0.0, yaw=0.0, mirrored=false, angle_offset=0.0))]
+    fn set_pose(&mut self, x: f32, y: f32, yaw: f32, mirrored: bool, angle_offset: f32) -> PyResult<()> {
+        self.dev.set_pose(SensorPose::new(x, y, yaw).mirrored(mirrored).angle_offset(angle_offset));
+        Ok(())
+    }
+
+    #[getter]
+    fn dropped_frames(&self) -> PyResult<u64> {
+        Ok(self.dev.dropped_frames())
+    }
+
+    // <port> is a serial port path, or a URI like "tcp://host:port" or "file://capture.d2".
+    // the other arguments set up the serial port and worker thread, with timeout in seconds, parity one of
+    // "none", "odd" or "even", and flow_control one of "none", "software" or "hardware".
+    #[pyo3(signature = (port, baud_rate=115200, timeout=0.02, parity="none", flow_control="none", dtr=None, rts=None, read_buffer_size=256, thread_name="lidar_decode_thread", thread_priority=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn open(
+        &mut self,
+        port: String,
+        baud_rate: u32,
+        timeout: f64,
+        parity: &str,
+        flow_control: &str,
+        dtr: Option<bool>,
+        rts: Option<bool>,
+        read_buffer_size: usize,
+        thread_name: &str,
+        thread_priority: Option<i32>,
+    ) -> PyResult<()> {
+        let parity = match parity {
+            "none" => Parity::None,
+            "odd" => Parity::Odd,
+            "even" => Parity::Even,
+            p => return Err(PyValueError::new_err(format!("unknown parity '{}'", p))),
+        };
+        let flow_control = match flow_control {
+            "none" => FlowControl::None,
+            "software" => FlowControl::Software,
+            "hardware" => FlowControl::Hardware,
+            f => return Err(PyValueError::new_err(format!("unknown flow control '{}'", f))),
+        };
+
+        let config = LidarConfig {
+            baud_rate,
+            timeout: seconds(timeout)?,
+            parity,
+            flow_control,
+            dtr,
+            rts,
+            read_buffer_size,
+            thread_name: thread_name.to_string(),
+            thread_priority,
+        };
+        self.dev.open_with(port, config)?;
+        Ok(())
+    }
+
+    // starts writing everything received into a capture file at <path>, which open_replay can play back
+    fn start_recording(&mut self, path: String) -> PyResult<()> {
+        self.dev.start_recording(path)?;
+        Ok(())
+    }
+
+    fn stop_recording(&mut self) -> PyResult<()> {
+        self.dev.stop_recording()?;
+        Ok(())
+    }
+
+    #[getter]
+    fn recording(&self) -> PyResult<bool> {
+        Ok(self.dev.recording())
+    }
+
+    // reads a capture file (from start_recording, or a raw dump of the serial port) instead of a serial port.
+    // speed=1.0 plays it back at the rate it was recorded, and speed=0.0 as fast as possible.
+    #[pyo3(signature = (path, speed=1.0))]
+    fn open_replay(&mut self, path: String, speed: f64) -> PyResult<()> {
+        self.dev.open_replay(path, speed)?;
+        Ok(())
+    }
+
+    // reads a frame, waiting forever if timeout is None, otherwise raising ReadTimeoutError after <timeout> seconds
+    #[pyo3(signature = (timeout=None))]
+    fn read_frame(&mut self, py: Python<'_>, timeout: Option<f64>) -> PyResult<PyMeasurementFrame> {
+        match recv_interruptible(py, &mut self.dev, timeout, Lidar::recv_timeout) {
+            Ok(msg) => {
+                // need to turn into pyobject
+                let pymsg : PyMeasurementFrame = PyMeasurementFrame { frame: msg };
+                Ok(pymsg)
+            },
+            Err(e) => {
+                Err(e)
+            }
+        }
+    }
+
+    #[pyo3(signature = (timeout=None))]
+    fn read_event(&mut self, py: Python<'_>, timeout: Option<f64>) -> PyResult<PyObject> {
+        // reads either a MeasurementFrame, HealthFrame or ConnectionEvent
+        let event = recv_interruptible(py, &mut self.dev, timeout, Lidar::recv_event_timeout)?;
+        Ok(event_object(py, event))
+    }
+
+    #[pyo3(signature = (timeout=None))]
+    fn read_full_scan(&mut self, py: Python<'_>, timeout: Option<f64>) -> PyResult<PyFullScan> {
+        match recv_interruptible(py, &mut self.dev, timeout, Lidar::recv_fullscan_timeout) {
+            Ok(msg) => {
+                // need to turn into pyobject
+                let pymsg : PyFullScan = PyFullScan { scan: msg };
+                Ok(pymsg)
+            },
+            Err(e) => {
+                Err(e)
+            }
+        }
+    }
+
+    fn alive(&self) -> bool {
+        self.dev.alive()
+    }
+
+    #[getter]
+    fn stats(&self) -> PyResult<PyDecoderStats> {
+        Ok(PyDecoderStats { stats: self.dev.stats() })
+    }
+}
+
+#[pymethods]
+impl PyMeasurementFrame {
+    #[new]
+    fn new() -> PyResult<Self> {
+        let m = MeasurementFrame::default();
+        Ok(PyMeasurementFrame{frame: m})
+    }
+
+    #[getter]
+    fn rpm(&self) -> PyResult<f32> {
+        Ok(self.frame.rpm)
+    }
+
+    #[getter]
+    fn offset_angle(&self) -> PyResult<f32> {
+        Ok(self.frame.offset_angle)
+    }
+
+    #[getter]
+    fn start_angle(&self) -> PyResult<f32> {
+        Ok(self.frame.start_angle)
+    }
+
+    #[getter]
+    fn sector(&self) -> PyResult<usize> {
+        Ok(self.frame.sector())
+    }
+
+    #[getter]
+    fn sector_angle(&self) -> PyResult<f32> {
+        Ok(self.frame.sector_angle())
+    }
+
+    #[getter]
+    fn end_angle(&self) -> PyResult<f32> {
+        let s = self.frame.start_angle;
+        let o = (self.frame.measurements.len() as f32) * self.frame.offset_angle;
+        Ok(s + o)
+    }
+
+    #[getter]
+    fn timestamp(&self) -> PyResult<u128> {
+        Ok(self.frame.timestamp)
+    }
+
+    #[getter]
+    fn measurements(&self) -> PyResult<Vec<PyMeasurement>> {
+        Ok(self.frame.measurements.iter().map(|m|{ PyMeasurement{m:m.clone(), pose:self.frame.pose}}).collect::<Vec<PyMeasurement>>())
+    }
+
+    #[getter]
+    fn points(&self) -> PyResult<Vec<(f32,f32)>> {
+        Ok(self.frame.points())
+    }
+
+    // like points, but as an N x 2 (or N x 3 with z=True) numpy array
+    #[pyo3(signature = (z=false))]
+    fn points_array(&self, py: Python<'_>, z: bool) -> PyResult<PyObject> {
+        points_array(py, std::iter::once(&self.frame), z)
+    }
+
+    // N x 3 numpy array of (angle, distance_mm, signal_quality) columns
+    fn measurement_array(&self, py: Python<'_>) -> PyResult<PyObject> {
+        measurement_array(py, self.frame.measurements.iter())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.frame.to_string())
+    }
+
+    fn as_json(&self) -> PyResult<String> {
+        Ok(self.frame.as_json())
+    }
+
+    fn to_csv(&self) -> PyResult<String> {
+        Ok(self.frame.to_csv())
+    }
+
+    // the frame as the lidar would have sent it over the wire
+    fn to_wire_bytes<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        Ok(PyBytes::new(py, &self.frame.to_wire_bytes()?))
+    }
+}
+
+#[pymethods]
+impl PyHealthFrame {
+    #[getter]
+    fn rpm(&self) -> PyResult<f32> {
+        Ok(self.frame.rpm)
+    }
+
+    #[getter]
+    fn error_codes(&self) -> PyResult<Vec<u8>> {
+        Ok(self.frame.error_codes.clone())
+    }
+
+    #[getter]
+    fn timestamp(&self) -> PyResult<u128> {
+        Ok(self.frame.timestamp)
+    }
+
+    #[getter]
+    fn status(&self) -> PyResult<String> {
+        Ok(self.frame.status().to_string())
+    }
+
+    #[getter]
+    fn motor_stalled(&self) -> PyResult<bool> {
+        Ok(self.frame.motor_stalled())
+    }
+
+    #[getter]
+    fn motor_speed_low(&self) -> PyResult<bool> {
+        Ok(self.frame.motor_speed_low())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.frame.to_string())
+    }
+
+    fn as_json(&self) -> PyResult<String> {
+        Ok(self.frame.as_json())
+    }
+
+    // the frame as the lidar would have sent it over the wire
+    fn to_wire_bytes<'py>(&self, py: Python<'py>) -> PyResult<&'py PyBytes> {
+        Ok(PyBytes::new(py, &self.frame.to_wire_bytes()?))
+    }
+}
+
+#[pymethods]
+impl PyConnectionEvent {
+    #[getter]
+    fn connected(&self) -> PyResult<bool> {
+        Ok(self.connected)
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(if self.connected { "reconnected" } else { "disconnected" }.to_string())
+    }
+}
+
+#[pymethods]
+impl PyDecoderStats {
+    #[getter]
+    fn bytes_received(&self) -> PyResult<u64> {
+        Ok(self.stats.bytes_received)
+    }
+
+    #[getter]
+    fn frames_ok(&self) -> PyResult<u64> {
+        Ok(self.stats.frames_ok)
+    }
+
+    #[getter]
+    fn measurement_frames(&self) -> PyResult<u64> {
+        Ok(self.stats.measurement_frames)
+    }
+
+    #[getter]
+    fn health_frames(&self) -> PyResult<u64> {
+        Ok(self.stats.health_frames)
+    }
+
+    #[getter]
+    fn crc_failures(&self) -> PyResult<u64> {
+        Ok(self.stats.crc_failures)
+    }
+
+    #[getter]
+    fn header_resyncs(&self) -> PyResult<u64> {
+        Ok(self.stats.header_resyncs)
+    }
+
+    #[getter]
+    fn bytes_discarded(&self) -> PyResult<u64> {
+        Ok(self.stats.bytes_discarded)
+    }
+
+    #[getter]
+    fn byte_rate(&self) -> PyResult<f32> {
+        Ok(self.stats.byte_rate)
+    }
+
+    #[getter]
+    fn frame_rate(&self) -> PyResult<f32> {
+        Ok(self.stats.frame_rate)
+    }
+
+    #[getter]
+    fn crc_failure_rate(&self) -> PyResult<f32> {
+        Ok(self.stats.crc_failure_rate)
+    }
+
+    #[getter]
+    fn discard_rate(&self) -> PyResult<f32> {
+        Ok(self.stats.discard_rate)
+    }
+
+    #[getter]
+    fn crc_error_ratio(&self) -> PyResult<f32> {
+        Ok(self.stats.crc_error_ratio())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.stats.to_string())
+    }
+
+    fn as_json(&self) -> PyResult<String> {
+        Ok(self.stats.as_json())
+    }
+}
+
+#[pymethods]
+impl PyFullScan {
+    #[getter]
+    fn frames(&self) -> PyResult<Vec<PyMeasurementFrame>> {
+        Ok(self.scan.frames.iter().map(|f| PyMeasurementFrame{frame:f.clone()}).collect::<Vec<PyMeasurementFrame>>())
+    }
+
+    #[getter]
+    fn points(&self) -> PyResult<Vec<(f32,f32)>> {
+        Ok(self.scan.points())
+    }
+
+    // like points, but as an N x 2 (or N x 3 with z=True) numpy array
+    #[pyo3(signature = (z=false))]
+    fn points_array(&self, py: Python<'_>, z: bool) -> PyResult<PyObject> {
+        points_array(py, self.scan.frames.iter(), z)
+    }
+
+    // N x 3 numpy array of (angle, distance_mm, signal_quality) columns
+    fn measurement_array(&self, py: Python<'_>) -> PyResult<PyObject> {
+        measurement_array(py, self.scan.frames.iter().flat_map(|f| f.measurements.iter()))
+    }
+
+    #[getter]
+    fn timestamp_range(&self) -> PyResult<i64> {
+        Ok(self.scan.timestamp_range())
+    }
+
+    #[getter]
+    fn timestamp(&self) -> PyResult<u128> {
+        Ok(self.scan.timestamp())
+    }
+
+    #[getter]
+    fn rpm(&self) -> PyResult<f32> {
+        Ok(self.scan.rpm())
+    }
+
+    #[getter]
+    fn complete(&self) -> PyResult<bool> {
+        Ok(self.scan.complete())
+    }
+
+    #[getter]
+    fn missing_sectors(&self) -> PyResult<Vec<usize>> {
+        Ok(self.scan.missing_sectors())
+    }
+
+    // resamples the scan onto evenly spaced angles (in degrees), with <policy> one of
+    // "nearest", "min" or "mean" deciding what happens when several measurements fall in the same bin.
+    #[pyo3(signature = (angle_min=0.0, angle_max=360.0, increment=1.0, policy="nearest"))]
+    fn to_range_array(&self, angle_min: f32, angle_max: f32, increment: f32, policy: &str) -> PyResult<PyRangeArray> {
+        let policy = match policy {
+            "nearest" => BinPolicy::Nearest,
+            "min" => BinPolicy::Min,
+            "mean" => BinPolicy::Mean,
+            p => return Err(PyValueError::new_err(format!("unknown bin policy '{}'", p))),
+        };
+        Ok(PyRangeArray { ranges: self.scan.to_range_array(angle_min, angle_max, increment, policy)? })
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.scan.to_string())
+    }
+    
+    fn as_json(&self) -> PyResult<String> {
+        Ok(self.scan.as_json())
+    }
+
+    // the measurements as CSV text, with a header row, e.g. for pandas.read_csv(io.StringIO(...))
+    fn to_csv(&self) -> PyResult<String> {
+        Ok(self.scan.to_csv())
+    }
+}
+
+#[pymethods]
+impl PyRangeArray {
+    #[getter]
+    fn angle_min(&self) -> PyResult<f32> {
+        Ok(self.ranges.angle_min)
+    }
+
+    #[getter]
+    fn angle_max(&self) -> PyResult<f32> {
+        Ok(self.ranges.angle_max)
+    }
+
+    #[getter]
+    fn angle_increment(&self) -> PyResult<f32> {
+        Ok(self.ranges.angle_increment)
+    }
+
+    #[getter]
+    fn timestamp(&self) -> PyResult<u128> {
+        Ok(self.ranges.timestamp)
+    }
+
+    #[getter]
+    fn ranges(&self, py: Python<'_>) -> PyResult<PyObject> {
+        numpy_array(py, self.ranges.ranges.clone(), vec![self.ranges.ranges.len()])
+    }
+
+    #[getter]
+    fn intensities(&self, py: Python<'_>) -> PyResult<PyObject> {
+        numpy_array(py, self.ranges.intensities.clone(), vec![self.ranges.intensities.len()])
+    }
+
+    fn __len__(&self) -> usize {
+        self.ranges.ranges.len()
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.ranges.to_string())
+    }
+
+    fn as_json(&self) -> PyResult<String> {
+        Ok(self.ranges.as_json())
+    }
+}
+
+#[pymethods]
+impl PyDiscoveredPort {
+    #[getter]
+    fn path(&self) -> PyResult<String> {
+        Ok(self.port.path.clone())
+    }
+
+    #[getter]
+    fn vid(&self) -> PyResult<Option<u16>> {
+        Ok(self.port.vid)
+    }
+
+    #[getter]
+    fn pid(&self) -> PyResult<Option<u16>> {
+        Ok(self.port.pid)
+    }
+
+    #[getter]
+    fn frames(&self) -> PyResult<u64> {
+        Ok(self.port.frames)
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.port.path.clone())
+    }
+}
+
+// finds the serial ports with a Delta-2 attached, by listening on each for up to <timeout> seconds.
+// <usb_ids> is a list of (vid, pid) tuples to filter by, and <ports> a list of ports to check instead of all of them.
+#[pyfunction]
+#[pyo3(name = "discover", signature = (timeout=0.5, usb_ids=None, ports=None, baud_rate=115200, min_frames=3))]
+fn py_discover(py: Python<'_>, timeout: f64, usb_ids: Option<Vec<(u16, u16)>>, ports: Option<Vec<String>>, baud_rate: u32, min_frames: u64) -> PyResult<Vec<PyDiscoveredPort>> {
+    let options = DiscoveryOptions {
+        listen: seconds(timeout)?,
+        min_frames,
+        usb_ids: usb_ids.unwrap_or_default(),
+        ports,
+        config: LidarConfig { baud_rate, ..LidarConfig::default() },
+    };
+    let found = py.allow_threads(|| discover::discover(&options))?;
+    Ok(found.into_iter().map(|port| PyDiscoveredPort { port }).collect())
+}
+
+// writes <data> (a MeasurementFrame or FullScan, or a list of them) to a CSV file at <path>, one row per measurement
+#[pyfunction]
+#[pyo3(name = "to_csv")]
+fn py_to_csv(path: String, data: &PyAny) -> PyResult<()> {
+    let items = match data.is_instance_of::<PyMeasurementFrame>() || data.is_instance_of::<PyFullScan>() {
+        true => vec![data],
+        false => data.iter()?.collect::<PyResult<Vec<_>>>()?,
+    };
+    enum Item<'a> {
+        Frame(PyRef<'a, PyMeasurementFrame>),
+        Scan(PyRef<'a, PyFullScan>),
+    }
+    // checked before creating the file, so that a bad item doesn't leave it truncated
+    let items = items
+        .into_iter()
+        .map(|item| {
+            if let Ok(f) = item.extract() {
+                Ok(Item::Frame(f))
+            } else if let Ok(s) = item.extract() {
+                Ok(Item::Scan(s))
+            } else {
+                Err(PyTypeError::new_err("expected MeasurementFrames or FullScans"))
+            }
+        })
+        .collect::<PyResult<Vec<_>>>()?;
+
+    let mut writer = CsvWriter::new(BufWriter::new(File::create(path)?))?;
+    for item in items {
+        match item {
+            Item::Frame(f) => writer.write_frame(&f.frame)?,
+            Item::Scan(s) => writer.write_scan(&s.scan)?,
+        }
+    }
+    Ok(writer.flush()?)
+}
+
+// reads the frames back from a CSV file written by to_csv. The rpm isn't stored, so is 0
+#[pyfunction]
+#[pyo3(name = "from_csv")]
+fn py_from_csv(path: String) -> PyResult<Vec<PyMeasurementFrame>> {
+    let mut reader = CsvReader::new(BufReader::new(File::open(path)?))?;
+    let mut frames = vec![];
+    while let Some(frame) = reader.next_frame()? {
+        frames.push(PyMeasurementFrame { frame });
+    }
+    Ok(frames)
+}
+
+#[pymethods]
+impl PyLidarGroup {
+    #[new]
+    fn new() -> PyResult<Self> {
+        Ok(PyLidarGroup { group: LidarGroup::new() })
+    }
+
+    // adds a lidar as <id>, mounted as in Lidar.set_pose.
+    // <lidar> is either a port/URI to open, or an opened Lidar, which is moved into the group (leaving it closed).
+    #[pyo3(signature = (id, lidar, x=0.0, y=0.0, yaw=0.0, mirrored=false, angle_offset=0.0))]
+    #[allow(clippy::too_many_arguments)]
+    fn add(&mut self, id: String, lidar: &PyAny, x: f32, y: f32, yaw: f32, mirrored: bool, angle_offset: f32) -> PyResult<()> {
+        let pose = SensorPose::new(x, y, yaw).mirrored(mirrored).angle_offset(angle_offset);
+        if let Ok(path) = lidar.extract::<String>() {
+            self.group.open(id, path, pose)?;
+        } else {
+            let mut lidar: PyRefMut<'_, PyLidar> = lidar.extract()?;
+            self.group.add(id, std::mem::take(&mut lidar.dev), pose)?;
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> PyResult<()> {
+        self.group.start()?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> PyResult<()> {
+        self.group.close()?;
+        Ok(())
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(&mut self, exc_type: Option<&PyAny>, _exc_value: Option<&PyAny>, _traceback: Option<&PyAny>) -> PyResult<bool> {
+        close_on_exit(self.group.close(), exc_type)?;
+        Ok(false)
+    }
+
+    #[getter]
+    fn ids(&self) -> PyResult<Vec<String>> {
+        Ok(self.group.ids())
+    }
+
+    // both in seconds, see LidarGroup::set_merge_window and set_max_skew
+    fn set_merge_window(&mut self, window: f64) -> PyResult<()> {
+        self.group.set_merge_window(seconds(window)?);
+        Ok(())
+    }
+
+    fn set_max_skew(&mut self, skew: f64) -> PyResult<()> {
+        self.group.set_max_skew(seconds(skew)?);
+        Ok(())
+    }
+
+    // returns (id, event) for the next event from any lidar, in timestamp order.
+    // the event is a MeasurementFrame, HealthFrame or ConnectionEvent, as with Lidar.read_event
+    #[pyo3(signature = (timeout=None))]
+    fn read_event(&mut self, py: Python<'_>, timeout: Option<f64>) -> PyResult<(String, PyObject)> {
+        let e = recv_interruptible(py, &mut self.group, timeout, LidarGroup::recv_event_timeout)?;
+        Ok((e.device, event_object(py, e.event)))
+    }
+
+    #[pyo3(signature = (timeout=None))]
+    fn read_scan(&mut self, py: Python<'_>, timeout: Option<f64>) -> PyResult<PyGroupScan> {
+        let scan = recv_interruptible(py, &mut self.group, timeout, LidarGroup::recv_scan_timeout)?;
+        Ok(PyGroupScan { scan })
+    }
+
+    fn alive(&self) -> bool {
+        self.group.alive()
+    }
+}
+
+#[pymethods]
+impl PyGroupScan {
+    // (id, FullScan) for each lidar, with the points still in the lidar's own frame
+    #[getter]
+    fn scans(&self) -> PyResult<Vec<(String, PyFullScan)>> {
+        Ok(self.scan.scans.iter().map(|s| (s.device.clone(), PyFullScan { scan: s.scan.clone() })).collect())
+    }
+
+    // every lidar's points, in the robot's frame
+    #[getter]
+    fn points(&self) -> PyResult<Vec<(f32,f32)>> {
+        Ok(self.scan.points())
+    }
+
+    #[pyo3(signature = (z=false))]
+    fn points_array(&self, py: Python<'_>, z: bool) -> PyResult<PyObject> {
+        points_array(py, self.scan.scans.iter().flat_map(|s| s.scan.frames.iter()), z)
+    }
+
+    #[getter]
+    fn timestamp(&self) -> PyResult<u128> {
+        Ok(self.scan.timestamp())
+    }
+
+    #[getter]
+    fn timestamp_range(&self) -> PyResult<i64> {
+        Ok(self.scan.timestamp_range())
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.scan.to_string())
+    }
+
+    fn as_json(&self) -> PyResult<String> {
+        Ok(self.scan.as_json())
+    }
+}
+
+#[pymethods]
+impl PyMeasurement {
+    #[getter]
+    fn angle(&self) -> PyResult<f32> {
+        Ok(self.m.angle)
+    }
+
+    #[getter]
+    fn signal_quality(&self) -> PyResult<u8> {
+        Ok(self.m.signal_quality)
+    }
+
+    #[getter]
+    fn distance_mm(&self) -> PyResult<f32> {
+        Ok(self.m.distance_mm)
+    }
+
+    // in the lidar's own frame, as in rust
+    #[getter]
+    fn point(&self) -> PyResult<(f32,f32)> {
+        Ok(self.m.point())
+    }
+
+    // in the robot frame, if the lidar has a pose set
+    #[getter]
+    fn robot_point(&self) -> PyResult<(f32,f32)> {
+        Ok(self.m.point_in(&self.pose))
+    }
+
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.m.to_string())
+    }
+}
+
+#[pymodule]
+fn delta2_lidar_py(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyLidar>()?;
+    m.add_class::<PyMeasurementFrame>()?;
+    m.add_class::<PyMeasurement>()?;
+    m.add_class::<PyFullScan>()?;
+    m.add_class::<PyHealthFrame>()?;
+    m.add_class::<PyDecoderStats>()?;
+    m.add_class::<PyConnectionEvent>()?;
+    m.add_class::<PyRangeArray>()?;
+    m.add_class::<PyDiscoveredPort>()?;
+    m.add_class::<PyLidarGroup>()?;
+    m.add_class::<PyGroupScan>()?;
+    m.add_function(wrap_pyfunction!(py_discover, m)?)?;
+    m.add_function(wrap_pyfunction!(py_to_csv, m)?)?;
+    m.add_function(wrap_pyfunction!(py_from_csv, m)?)?;
+    m.add("LidarError", py.get_type::<LidarError>())?;
+    m.add("SerialOpenError", py.get_type::<SerialOpenError>())?;
+    m.add("DisconnectedError", py.get_type::<DisconnectedError>())?;
+    m.add("HealthFaultError", py.get_type::<HealthFaultError>())?;
+    m.add("ReadTimeoutError", py.get_type::<ReadTimeoutError>())?;
+    m.add("WorkerPanickedError", py.get_type::<WorkerPanickedError>())?;
+    Ok(())
+}
+
+
@@ -0,0 +1,211 @@
+// Queue used to pass events from the worker thread to the Lidar.
+// It works like std::sync::mpsc::channel, but can optionally be bounded, with a choice of what
+// happens when the consumer falls behind and the queue fills up.
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvTimeoutError, SendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    DropOldest, // throw away the oldest queued item, so the consumer always gets the freshest data
+    DropNewest, // throw away the item being sent
+    Block, // make the sender wait until there is room
+}
+
+// Whether an item can be thrown away when the queue is full. One that can't (e.g. a connection event) is
+// always queued, throwing away the oldest item that can instead, or going over capacity if there are none.
+pub trait Droppable {
+    fn droppable(&self) -> bool;
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    dropped: u64,
+    sender_alive: bool,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<usize>, // None is unbounded
+    policy: OverflowPolicy,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().expect("queue lock poisoned")
+    }
+}
+
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// creates a new queue. A capacity of None is unbounded, and the policy is never used.
+pub fn queue<T: Droppable>(capacity: Option<usize>, policy: OverflowPolicy) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            dropped: 0,
+            sender_alive: true,
+            receiver_alive: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        // a zero-sized queue could never hold anything
+        capacity: capacity.map(|c| c.max(1)),
+        policy,
+    });
+
+    (
+        QueueSender { shared: shared.clone() },
+        QueueReceiver { shared },
+    )
+}
+
+impl<T: Droppable> QueueSender<T> {
+    // queues an item, applying the overflow policy if the queue is full.
+    // fails (returning the item) only if the receiver has gone away.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.lock();
+
+        if let Some(capacity) = self.shared.capacity {
+            while state.receiver_alive && state.items.len() >= capacity {
+                match self.shared.policy {
+                    OverflowPolicy::DropNewest if item.droppable() => {
+                        state.dropped += 1;
+                        return Ok(());
+                    }
+                    OverflowPolicy::DropOldest | OverflowPolicy::DropNewest => {
+                        match state.items.iter().position(|i| i.droppable()) {
+                            Some(oldest) => {
+                                state.items.remove(oldest);
+                                state.dropped += 1;
+                            }
+                            // only connection events are queued, which are never dropped
+                            None if item.droppable() => {
+                                state.dropped += 1;
+                                return Ok(());
+                            }
+                            None => break,
+                        }
+                    }
+                    OverflowPolicy::Block => {
+                        state = self.shared.not_full.wait(state).expect("queue lock poisoned");
+                    }
+                }
+            }
+        }
+
+        if !state.receiver_alive {
+            return Err(SendError(item));
+        }
+
+        state.items.push_back(item);
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().sender_alive = false;
+        self.shared.not_empty.notify_all();
+    }
+}
+
+impl<T> QueueReceiver<T> {
+    // waits forever for the next item, failing only once the sender has gone away and the queue is empty.
+    pub fn recv(&self) -> Result<T, RecvTimeoutError> {
+        self.recv_until(None)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Instant::now().checked_add(timeout))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut state = self.shared.lock();
+
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.shared.not_full.notify_one();
+                return Ok(item);
+            }
+
+            if !state.sender_alive {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            state = match deadline {
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.shared.not_empty.wait_timeout(state, d - now).expect("queue lock poisoned").0
+                }
+                None => self.shared.not_empty.wait(state).expect("queue lock poisoned"),
+            };
+        }
+    }
+
+    // number of items waiting to be received
+    pub fn len(&self) -> usize {
+        self.shared.lock().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // number of (droppable) items thrown away because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_alive = false;
+        // wake up a sender blocked on a full queue
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // odd numbers stand in for connection events
+    impl Droppable for u32 {
+        fn droppable(&self) -> bool {
+            self.is_multiple_of(2)
+        }
+    }
+
+    fn drain(rx: &QueueReceiver<u32>) -> Vec<u32> {
+        std::iter::from_fn(|| rx.recv_timeout(Duration::ZERO).ok()).collect()
+    }
+
+    #[test]
+    fn full_queue_keeps_connection_events() {
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let (tx, rx) = queue(Some(2), policy);
+            // once only connection events are left, they go over capacity, and new frames are dropped
+            for item in [2, 4, 6, 1, 3, 5, 8] {
+                tx.send(item).unwrap();
+            }
+            assert_eq!(drain(&rx), vec![1, 3, 5], "{:?}", policy);
+            assert_eq!(rx.dropped(), 4, "{:?}", policy);
+        }
+    }
+}
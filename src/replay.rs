@@ -0,0 +1,198 @@
+// Plays back recordings of the lidar's UART output, so the decoder and Lidar can be run against
+// recorded data, without the hardware. Either a raw capture (e.g. `cat /dev/ttyUSB0 > capture.bin`),
+// or a capture file with receive timestamps, as written by Lidar::start_recording.
+use crate::capture::CaptureReader;
+
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 8N1 framing puts 10 bits on the wire per byte
+const BITS_PER_BYTE: f64 = 10.0;
+
+// Wraps a reader of raw bytes, handing them out no faster than a serial port at baud_rate would have.
+// speed scales the playback rate: 1.0 is real-time, 2.0 twice as fast, and 0.0 as fast as possible.
+pub struct ReplaySource<R> {
+    inner: R,
+    speed: f64,
+    baud_rate: u32,
+    started: Option<Instant>,
+    bytes_read: u64,
+}
+
+impl<R: Read> ReplaySource<R> {
+    pub fn new(inner: R, speed: f64, baud_rate: u32) -> Self {
+        ReplaySource {
+            inner,
+            speed,
+            baud_rate,
+            started: None,
+            bytes_read: 0,
+        }
+    }
+
+    // bytes per second, after scaling by speed
+    fn byte_rate(&self) -> f64 {
+        (self.baud_rate as f64) / BITS_PER_BYTE * self.speed
+    }
+}
+
+impl<R: Read> Read for ReplaySource<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.speed <= 0.0 {
+            return self.inner.read(buf);
+        }
+
+        // read about 20ms worth of data at a time, so the output is smooth, and the caller
+        // gets to check if it should stop fairly often
+        let chunk = ((self.byte_rate() * 0.02) as usize).max(1).min(buf.len());
+
+        // wait until the last of these bytes would have arrived
+        let started = *self.started.get_or_insert_with(Instant::now);
+        wait_until(due_after(started, ((self.bytes_read + chunk as u64) as f64) / self.byte_rate()))?;
+
+        let n = self.inner.read(&mut buf[..chunk])?;
+        self.bytes_read += n as u64;
+        Ok(n)
+    }
+}
+
+// when something <seconds> after <start> is due, or None if that's too far off to represent (e.g. at a tiny speed)
+pub(crate) fn due_after(start: Instant, seconds: f64) -> Option<Instant> {
+    Duration::try_from_secs_f64(seconds).ok().and_then(|d| start.checked_add(d))
+}
+
+// waits for <due> in small steps, timing out like a serial port would until it comes, so that the caller
+// can still stop during long waits. None is never due.
+pub(crate) fn wait_until(due: Option<Instant>) -> io::Result<()> {
+    let now = Instant::now();
+    if due.map(|due| due > now).unwrap_or(true) {
+        let wait = due.map(|due| due - now).unwrap_or(Duration::MAX).min(Duration::from_millis(20));
+        thread::sleep(wait);
+        if due.map(|due| now + wait < due).unwrap_or(true) {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+    }
+    Ok(())
+}
+
+// Plays back a capture file (see capture.rs), handing out each chunk at the same
+// relative time it was originally received, scaled by speed as above.
+pub struct CaptureReplay<R: Read> {
+    reader: CaptureReader<R>,
+    speed: f64,
+    chunk: Vec<u8>,
+    position: usize, // how much of chunk has been handed out
+    timestamp: u128, // when chunk was originally received
+    started: Option<(Instant, u128)>, // when the first chunk was handed out, and its timestamp
+    waiting: bool, // whether chunk is waiting until due to be handed out
+    due: Option<Instant>, // when chunk should be handed out, None if it's too far off to ever be
+}
+
+impl<R: Read> CaptureReplay<R> {
+    pub fn new(reader: CaptureReader<R>, speed: f64) -> Self {
+        CaptureReplay {
+            reader,
+            speed,
+            chunk: vec![],
+            position: 0,
+            timestamp: 0,
+            started: None,
+            waiting: false,
+            due: None,
+        }
+    }
+
+    // the original receive timestamp of the bytes most recently read
+    pub fn timestamp(&self) -> u128 {
+        self.timestamp
+    }
+}
+
+impl<R: Read> Read for CaptureReplay<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.chunk.len() {
+            let (timestamp, data) = match self.reader.next_chunk()? {
+                Some(c) => c,
+                None => return Ok(0),
+            };
+            self.chunk = data;
+            self.position = 0;
+            self.timestamp = timestamp;
+
+            if self.speed > 0.0 {
+                let (start, start_timestamp) = *self.started.get_or_insert((Instant::now(), timestamp));
+                let offset = (timestamp.saturating_sub(start_timestamp) as f64) / 1e9 / self.speed;
+                self.due = due_after(start, offset);
+                self.waiting = true;
+            }
+        }
+
+        if self.waiting {
+            // the caller can still stop during long gaps in the recording
+            wait_until(self.due)?;
+            self.waiting = false;
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureWriter;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn raw_replay_paces_the_bytes() {
+        // 2 chunks of 20ms, at 115200 baud
+        let mut replay = ReplaySource::new(Cursor::new(vec![0u8; 460]), 1.0, 115200);
+        let mut buf = [0u8; 1024];
+        let started = Instant::now();
+        let mut total = 0;
+        loop {
+            match replay.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => total += n,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert_eq!(total, 460);
+        assert!(started.elapsed() >= Duration::from_millis(35));
+    }
+
+    fn capture(timestamps: &[u128]) -> CaptureReader<Cursor<Vec<u8>>> {
+        let mut data = vec![];
+        let mut writer = CaptureWriter::new(&mut data).unwrap();
+        for (i, timestamp) in timestamps.iter().enumerate() {
+            writer.write_chunk(*timestamp, &[i as u8]).unwrap();
+        }
+        CaptureReader::new(Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn capture_replay_at_a_tiny_speed_stops_after_the_first_chunk() {
+        let mut replay = CaptureReplay::new(capture(&[1_000_000_000, 2_000_000_000]), 1e-300);
+        let mut buf = [0u8; 10];
+        assert_eq!(replay.read(&mut buf).unwrap(), 1);
+        for _ in 0..3 {
+            assert_eq!(replay.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+        }
+    }
+
+    #[test]
+    fn raw_replay_at_a_tiny_speed_never_finishes() {
+        // so slow that the first byte is too far off to represent
+        let mut replay = ReplaySource::new(Cursor::new(vec![0u8; 10]), 1e-300, 115200);
+        let mut buf = [0u8; 10];
+        for _ in 0..3 {
+            assert_eq!(replay.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+        }
+    }
+}
@@ -0,0 +1,603 @@
+// Simulates the lidar moving through a 2D world, ray casting each measurement against the walls and objects in it,
+// for testing code downstream of the driver without the hardware (or a building to put it in).
+//
+// Frames come out the same as from the real lidar: 15 sectors of 24 degrees, with measurement angles spread
+// across each sector as the decoder spreads them, in the lidar's own frame (the trajectory says where it is).
+//
+// let world = World::load("office.world")?;
+// let mut sim = Simulator::new(world, Trajectory::fixed(SensorPose::new(2.0, 1.5, 0.0)), SimConfig::new())?;
+// let scan = sim.next_scan();
+//
+// or as a byte source for the Lidar, in real time (or faster, or as fast as possible with speed 0.0):
+// lidar.open_transport(Box::new(sim.into_transport(1.0)?))?;
+//
+// World files have one shape per line, in metres. Polygons are closed, the last point joining the first:
+//   # a 4 x 3 metre room, with a pillar in it
+//   polygon 0 0  4 0  4 3  0 3
+//   circle 2.5 1 0.2
+use crate::emulator::XorShift;
+use crate::error::{Error, Result};
+use crate::frame::{RawFrame, MAX_MEASUREMENTS};
+use crate::pose::SensorPose;
+use crate::protocol::{get_nanos, FullScan, Measurement, MeasurementFrame, SECTORS_PER_SCAN, SECTOR_ANGLE};
+use crate::replay::{due_after, wait_until};
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Instant;
+
+type Point = (f32, f32);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Polygon(Vec<Point>),
+    Circle { centre: Point, radius: f32 },
+}
+
+impl Shape {
+    // distance along the ray to where it first hits this shape, if it does. <direction> is a unit vector
+    fn cast(&self, origin: Point, direction: Point) -> Option<f32> {
+        match self {
+            Shape::Polygon(points) => {
+                let edges = points.iter().zip(points.iter().cycle().skip(1));
+                edges.filter_map(|(p, q)| cast_segment(origin, direction, *p, *q)).reduce(f32::min)
+            }
+            Shape::Circle { centre, radius } => {
+                let f = (origin.0 - centre.0, origin.1 - centre.1);
+                let b = f.0 * direction.0 + f.1 * direction.1;
+                let c = f.0 * f.0 + f.1 * f.1 - radius * radius;
+                let discriminant = b * b - c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                // the near side, or the far side if we're inside the circle
+                let s = discriminant.sqrt();
+                [-b - s, -b + s].into_iter().find(|t| *t >= 0.0)
+            }
+        }
+    }
+}
+
+fn cross(a: Point, b: Point) -> f32 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+fn cast_segment(origin: Point, direction: Point, p: Point, q: Point) -> Option<f32> {
+    let edge = (q.0 - p.0, q.1 - p.1);
+    let denominator = cross(direction, edge);
+    if denominator.abs() < 1e-9 {
+        // parallel
+        return None;
+    }
+    let to_p = (p.0 - origin.0, p.1 - origin.1);
+    let t = cross(to_p, edge) / denominator;
+    let u = cross(to_p, direction) / denominator;
+    (t >= 0.0 && (0.0..=1.0).contains(&u)).then_some(t)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct World {
+    pub shapes: Vec<Shape>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn polygon(mut self, points: Vec<Point>) -> Self {
+        self.shapes.push(Shape::Polygon(points));
+        self
+    }
+
+    pub fn circle(mut self, centre: Point, radius: f32) -> Self {
+        self.shapes.push(Shape::Circle { centre, radius });
+        self
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<World> {
+        World::parse(&fs::read_to_string(path)?)
+    }
+
+    // reads the world file format described at the top of this file
+    pub fn parse(text: &str) -> Result<World> {
+        let mut world = World::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let kind = match words.next() {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let numbers = words
+                .map(|w| w.parse::<f32>().ok().filter(|n| n.is_finite()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| Error::InvalidParameter(format!("line {}: expected numbers after '{}'", i + 1, kind)))?;
+
+            match kind {
+                "polygon" => {
+                    if numbers.len() < 4 || numbers.len() % 2 != 0 {
+                        return Err(Error::InvalidParameter(format!("line {}: a polygon needs at least 2 x y points", i + 1)));
+                    }
+                    world = world.polygon(numbers.chunks(2).map(|p| (p[0], p[1])).collect());
+                }
+                "circle" => {
+                    if numbers.len() != 3 || numbers[2] <= 0.0 {
+                        return Err(Error::InvalidParameter(format!("line {}: a circle needs x y radius", i + 1)));
+                    }
+                    world = world.circle((numbers[0], numbers[1]), numbers[2]);
+                }
+                _ => return Err(Error::InvalidParameter(format!("line {}: unknown shape '{}'", i + 1, kind))),
+            }
+        }
+        Ok(world)
+    }
+
+    // distance (in metres) along the ray to the nearest surface, if there is one. <direction> is a unit vector
+    pub fn cast(&self, origin: Point, direction: Point) -> Option<f32> {
+        self.shapes.iter().filter_map(|s| s.cast(origin, direction)).reduce(f32::min)
+    }
+}
+
+// Where the lidar is in the world over time, moving in straight lines between waypoints.
+// Before the first waypoint and after the last, it stays where they are.
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    waypoints: Vec<(f64, SensorPose)>, // seconds since the start of the simulation, and the pose then
+}
+
+impl Trajectory {
+    pub fn fixed(pose: SensorPose) -> Self {
+        Trajectory { waypoints: vec![(0.0, pose)] }
+    }
+
+    pub fn new(waypoints: Vec<(f64, SensorPose)>) -> Result<Self> {
+        if waypoints.is_empty() {
+            return Err(Error::InvalidParameter("a trajectory needs at least one waypoint".to_string()));
+        }
+        if waypoints.iter().any(|(t, _)| !t.is_finite()) || waypoints.windows(2).any(|w| w[1].0 <= w[0].0) {
+            return Err(Error::InvalidParameter("trajectory waypoint times must increase".to_string()));
+        }
+        Ok(Trajectory { waypoints })
+    }
+
+    // when the lidar stops moving, or None if it never moves
+    pub fn end(&self) -> Option<f64> {
+        match self.waypoints.len() {
+            1 => None,
+            _ => self.waypoints.last().map(|(t, _)| *t),
+        }
+    }
+
+    pub fn pose_at(&self, t: f64) -> SensorPose {
+        let i = match self.waypoints.iter().position(|(time, _)| *time > t) {
+            Some(0) => return self.waypoints[0].1,
+            Some(i) => i,
+            None => return self.waypoints[self.waypoints.len() - 1].1,
+        };
+        let (t0, a) = self.waypoints[i - 1];
+        let (t1, b) = self.waypoints[i];
+
+        let f = ((t - t0) / (t1 - t0)) as f32;
+        // turning whichever way round is shorter
+        let turn = (b.yaw - a.yaw + 540.0).rem_euclid(360.0) - 180.0;
+        SensorPose {
+            x: a.x + (b.x - a.x) * f,
+            y: a.y + (b.y - a.y) * f,
+            yaw: a.yaw + turn * f,
+            ..a
+        }
+    }
+}
+
+// How the simulated measurements differ from the true distances
+#[derive(Debug, Clone)]
+pub struct NoiseModel {
+    pub range_sigma_mm: f32, // standard deviation of the (gaussian) noise added to each distance
+    pub dropout_probability: f64, // chance of a measurement coming back empty, e.g. off a dark or shiny surface
+    pub min_range_mm: f32, // anything closer comes back empty
+    pub max_range_mm: f32, // as does anything further away, or nothing at all
+    pub quality_near: u8, // signal quality up close, falling off linearly with distance to...
+    pub quality_far: u8, // the signal quality at max_range_mm
+}
+
+impl Default for NoiseModel {
+    fn default() -> Self {
+        NoiseModel {
+            range_sigma_mm: 10.0,
+            dropout_probability: 0.01,
+            min_range_mm: 150.0,
+            max_range_mm: 8000.0,
+            quality_near: 220,
+            quality_far: 40,
+        }
+    }
+}
+
+impl NoiseModel {
+    // exact distances, and nothing lost within range
+    pub fn ideal() -> Self {
+        NoiseModel { range_sigma_mm: 0.0, dropout_probability: 0.0, ..Default::default() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub rpm: f32, // 0 to 765 (the protocol sends it in steps of 3)
+    pub points_per_sector: usize,
+    pub noise: NoiseModel,
+    pub seed: u64, // for the noise, so that runs are repeatable
+    pub start_time: Option<u128>, // unix epoch nanoseconds of the first frame, or None for when the simulator is made
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            rpm: 300.0,
+            points_per_sector: 28,
+            noise: NoiseModel::default(),
+            seed: 1,
+            start_time: None,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rpm(mut self, rpm: f32) -> Self {
+        self.rpm = rpm;
+        self
+    }
+
+    pub fn points_per_sector(mut self, points: usize) -> Self {
+        self.points_per_sector = points;
+        self
+    }
+
+    pub fn noise(mut self, noise: NoiseModel) -> Self {
+        self.noise = noise;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn start_time(mut self, timestamp: u128) -> Self {
+        self.start_time = Some(timestamp);
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(self.rpm > 0.0 && self.rpm <= 765.0) {
+            return Err(Error::InvalidParameter(format!("rpm {} is outside 0 to 765", self.rpm)));
+        }
+        if !(1..=MAX_MEASUREMENTS).contains(&self.points_per_sector) {
+            return Err(Error::InvalidParameter(format!("points per sector {} is outside 1 to {}", self.points_per_sector, MAX_MEASUREMENTS)));
+        }
+        let noise = &self.noise;
+        if !(noise.range_sigma_mm >= 0.0 && noise.range_sigma_mm.is_finite()) {
+            return Err(Error::InvalidParameter(format!("invalid range noise {}", noise.range_sigma_mm)));
+        }
+        if !(0.0..=1.0).contains(&noise.dropout_probability) {
+            return Err(Error::InvalidParameter(format!("dropout probability {} is outside 0 to 1", noise.dropout_probability)));
+        }
+        if !(noise.min_range_mm >= 0.0 && noise.min_range_mm < noise.max_range_mm) {
+            return Err(Error::InvalidParameter(format!("invalid range limits {} to {}", noise.min_range_mm, noise.max_range_mm)));
+        }
+        Ok(())
+    }
+}
+
+pub struct Simulator {
+    world: World,
+    trajectory: Trajectory,
+    config: SimConfig,
+    start_time: u128,
+    frames: usize, // how many frames have been made
+    rng: XorShift,
+}
+
+impl Simulator {
+    pub fn new(world: World, trajectory: Trajectory, config: SimConfig) -> Result<Self> {
+        config.validate()?;
+        let start_time = config.start_time.unwrap_or_else(get_nanos);
+        let rng = XorShift(config.seed.max(1));
+        Ok(Simulator { world, trajectory, config, start_time, frames: 0, rng })
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn trajectory(&self) -> &Trajectory {
+        &self.trajectory
+    }
+
+    // how long the lidar takes to sweep one sector, in seconds
+    fn frame_interval(&self) -> f64 {
+        60.0 / (self.config.rpm as f64) / (SECTORS_PER_SCAN as f64)
+    }
+
+    // seconds since the start of the simulation, when the next frame's sweep starts
+    pub fn time(&self) -> f64 {
+        (self.frames as f64) * self.frame_interval()
+    }
+
+    // whether the lidar has reached the end of its trajectory. Never, if it doesn't move
+    pub fn finished(&self) -> bool {
+        self.trajectory.end().map(|end| self.time() > end).unwrap_or(false)
+    }
+
+    // the next frame, as the lidar would send it
+    pub fn next_raw(&mut self) -> RawFrame {
+        let t0 = self.time();
+        let start_angle = ((self.frames % SECTORS_PER_SCAN) as f32) * SECTOR_ANGLE;
+        let step = SECTOR_ANGLE / (self.config.points_per_sector as f32);
+        let period = 60.0 / (self.config.rpm as f64);
+        self.frames += 1;
+
+        let measurements = (0..self.config.points_per_sector)
+            .map(|i| {
+                let angle = start_angle + (i as f32) * step;
+                // the lidar keeps moving during the sweep
+                let t = t0 + ((i as f32 * step) as f64) / 360.0 * period;
+                self.measure(angle, &self.trajectory.pose_at(t))
+            })
+            .collect::<Vec<_>>();
+
+        let timestamp = self.start_time + (t0 * 1e9) as u128;
+        RawFrame::encode_measurements(self.config.rpm, start_angle, &measurements, timestamp).expect("config was validated")
+    }
+
+    pub fn next_frame(&mut self) -> MeasurementFrame {
+        (&self.next_raw()).into()
+    }
+
+    // the frames up to the end of the current revolution, which is a whole one unless next_frame has been called part way through
+    pub fn next_scan(&mut self) -> FullScan {
+        let mut scan = FullScan::default();
+        loop {
+            scan.frames.push(self.next_frame());
+            if self.frames.is_multiple_of(SECTORS_PER_SCAN) {
+                return scan;
+            }
+        }
+    }
+
+    pub fn into_transport(self, speed: f64) -> Result<SimTransport> {
+        if !(speed.is_finite() && speed >= 0.0) {
+            return Err(Error::InvalidParameter(format!("speed {} must be 0 or more", speed)));
+        }
+        Ok(SimTransport { sim: self, speed, frame: vec![], position: 0, timestamp: 0, started: None })
+    }
+
+    fn measure(&mut self, angle: f32, pose: &SensorPose) -> Measurement {
+        // the direction of the ray, by where the pose puts a point 1 metre away
+        let (x, y) = pose.point(&Measurement { angle, signal_quality: 0, distance_mm: 1000.0 });
+        let direction = (x - pose.x, y - pose.y);
+        let distance = self.world.cast((pose.x, pose.y), direction).map(|d| d * 1000.0);
+
+        let noise = &self.config.noise;
+        let empty = Measurement { angle, signal_quality: 0, distance_mm: 0.0 };
+        let distance = match distance {
+            Some(d) if d >= noise.min_range_mm && d <= noise.max_range_mm => d,
+            _ => return empty,
+        };
+        if self.rng.chance(noise.dropout_probability) {
+            return empty;
+        }
+
+        let f = distance / noise.max_range_mm;
+        let quality = (noise.quality_near as f32) + ((noise.quality_far as f32) - (noise.quality_near as f32)) * f;
+        let distance_mm = (distance + noise.range_sigma_mm * self.gaussian()).max(0.0);
+        Measurement { angle, signal_quality: quality.round() as u8, distance_mm }
+    }
+
+    // standard normal, by the Box-Muller transform
+    fn gaussian(&mut self) -> f32 {
+        let u1 = self.rng.uniform().max(f64::MIN_POSITIVE);
+        let u2 = self.rng.uniform();
+        ((-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()) as f32
+    }
+}
+
+// Hands out the simulator's frames as bytes, each one once the lidar would have finished sweeping it,
+// scaled by speed like the replays: 1.0 is real-time, 2.0 twice as fast, and 0.0 as fast as possible.
+// Ends when the lidar reaches the end of its trajectory.
+pub struct SimTransport {
+    sim: Simulator,
+    speed: f64,
+    frame: Vec<u8>,
+    position: usize, // how much of frame has been handed out
+    timestamp: u128, // of frame
+    started: Option<Instant>,
+}
+
+impl SimTransport {
+    // the simulated timestamp of the frame most recently read
+    pub fn timestamp(&self) -> u128 {
+        self.timestamp
+    }
+
+    pub fn finite(&self) -> bool {
+        self.sim.trajectory.end().is_some()
+    }
+}
+
+impl Read for SimTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.frame.len() {
+            if self.sim.finished() {
+                return Ok(0);
+            }
+
+            if self.speed > 0.0 {
+                // wait in small steps, so that the caller can still stop
+                let started = *self.started.get_or_insert_with(Instant::now);
+                let swept = self.sim.time() + self.sim.frame_interval();
+                wait_until(due_after(started, swept / self.speed))?;
+            }
+
+            let raw = self.sim.next_raw();
+            self.frame = raw.as_bytes().to_vec();
+            self.timestamp = raw.timestamp;
+            self.position = 0;
+        }
+
+        let n = buf.len().min(self.frame.len() - self.position);
+        buf[..n].copy_from_slice(&self.frame[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // at the origin, facing along the robot's y axis, with exact distances and 1 degree between measurements
+    fn simulator(world: World, noise: NoiseModel) -> Simulator {
+        let config = SimConfig::new().points_per_sector(24).noise(noise).start_time(0);
+        Simulator::new(world, Trajectory::fixed(SensorPose::default()), config).unwrap()
+    }
+
+    // distances of a whole revolution, one per degree
+    fn distances(sim: &mut Simulator) -> Vec<f32> {
+        sim.next_scan().frames.iter().flat_map(|f| f.measurements.iter().map(|m| m.distance_mm)).collect()
+    }
+
+    #[test]
+    fn rays_hit_the_nearest_surface() {
+        let world = World::new().polygon(vec![(2.0, -10.0), (2.0, 10.0)]).circle((5.0, 0.0), 1.0);
+        assert_eq!(world.cast((0.0, 0.0), (1.0, 0.0)), Some(2.0));
+        assert_eq!(world.cast((3.0, 0.0), (1.0, 0.0)), Some(1.0));
+        // the far side, from inside the circle
+        assert_eq!(world.cast((5.0, 0.0), (1.0, 0.0)), Some(1.0));
+        assert_eq!(world.cast((0.0, 0.0), (-1.0, 0.0)), None);
+        assert_eq!(world.cast((0.0, 0.0), (0.0, 1.0)), None);
+    }
+
+    #[test]
+    fn measures_the_distance_to_walls() {
+        // 0 degrees is along +y, and 90 along +x
+        let world = World::parse("polygon 3 -10  3 10\npolygon -10 2  10 2").unwrap();
+        let distances = distances(&mut simulator(world, NoiseModel::ideal()));
+        assert_eq!(distances.len(), 360);
+        assert_eq!(distances[0], 2000.0);
+        assert_eq!(distances[90], 3000.0);
+        // nothing there
+        assert_eq!(distances[180], 0.0);
+        assert_eq!(distances[270], 0.0);
+    }
+
+    #[test]
+    fn parses_world_files() {
+        let world = World::parse("# a room\n\npolygon 0 0  4 0  4 3  0 3\ncircle 2.5 1 0.2 # pillar\n").unwrap();
+        assert_eq!(world, World::new().polygon(vec![(0.0, 0.0), (4.0, 0.0), (4.0, 3.0), (0.0, 3.0)]).circle((2.5, 1.0), 0.2));
+    }
+
+    #[test]
+    fn rejects_malformed_world_files() {
+        for text in [
+            "polygon 0 0",
+            "polygon 0 0  1",
+            "polygon 0 0  one 1",
+            "polygon 0 0  inf 1",
+            "circle 1 1",
+            "circle 1 1 0",
+            "circle 1 1 -1",
+            "triangle 0 0  1 0  0 1",
+        ] {
+            match World::parse(&format!("circle 0 0 1\n{}", text)) {
+                Err(Error::InvalidParameter(message)) => assert!(message.starts_with("line 2:"), "{}", message),
+                other => panic!("'{}' gave {:?}", text, other.map(|w| w.shapes)),
+            }
+        }
+    }
+
+    #[test]
+    fn trajectories_interpolate_between_waypoints() {
+        let trajectory = Trajectory::new(vec![(1.0, SensorPose::new(0.0, 0.0, 350.0)), (3.0, SensorPose::new(2.0, 4.0, 10.0))]).unwrap();
+        assert_eq!(trajectory.end(), Some(3.0));
+        assert_eq!(trajectory.pose_at(0.0), SensorPose::new(0.0, 0.0, 350.0));
+        assert_eq!(trajectory.pose_at(5.0), SensorPose::new(2.0, 4.0, 10.0));
+
+        let middle = trajectory.pose_at(2.0);
+        assert_eq!((middle.x, middle.y), (1.0, 2.0));
+        // turning the short way, through 0
+        assert!(middle.yaw.rem_euclid(360.0) < 1e-3);
+
+        assert!(Trajectory::new(vec![]).is_err());
+        assert!(Trajectory::new(vec![(1.0, SensorPose::default()), (1.0, SensorPose::default())]).is_err());
+        assert!(Trajectory::new(vec![(f64::NAN, SensorPose::default())]).is_err());
+        assert_eq!(Trajectory::fixed(SensorPose::default()).end(), None);
+    }
+
+    // surrounded by a circle, so every true distance is 2000mm
+    fn circle() -> World {
+        World::new().circle((0.0, 0.0), 2.0)
+    }
+
+    #[test]
+    fn noise_is_added_to_the_distances() {
+        let mut sim = simulator(circle(), NoiseModel { dropout_probability: 0.0, ..NoiseModel::default() });
+        let distances = (0..10).flat_map(|_| distances(&mut sim)).collect::<Vec<_>>();
+        let mean = distances.iter().sum::<f32>() / (distances.len() as f32);
+        let sigma = (distances.iter().map(|d| (d - mean).powi(2)).sum::<f32>() / (distances.len() as f32)).sqrt();
+        assert!((mean - 2000.0).abs() < 2.0, "mean {}", mean);
+        assert!((8.0..12.0).contains(&sigma), "sigma {}", sigma);
+    }
+
+    #[test]
+    fn noise_model_limits_and_quality() {
+        // all lost
+        let noise = NoiseModel { dropout_probability: 1.0, ..NoiseModel::ideal() };
+        assert!(distances(&mut simulator(circle(), noise)).iter().all(|d| *d == 0.0));
+
+        // out of range
+        let noise = NoiseModel { max_range_mm: 1500.0, ..NoiseModel::ideal() };
+        assert!(distances(&mut simulator(circle(), noise)).iter().all(|d| *d == 0.0));
+        let noise = NoiseModel { min_range_mm: 2500.0, max_range_mm: 8000.0, ..NoiseModel::ideal() };
+        assert!(distances(&mut simulator(circle(), noise)).iter().all(|d| *d == 0.0));
+
+        // a quarter of the way to max range, a quarter of the way from near to far quality
+        let noise = NoiseModel { max_range_mm: 8000.0, quality_near: 200, quality_far: 40, ..NoiseModel::ideal() };
+        let scan = simulator(circle(), noise).next_scan();
+        assert!(scan.frames.iter().flat_map(|f| f.measurements.iter()).all(|m| m.signal_quality == 160));
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        for config in [
+            SimConfig::new().rpm(0.0),
+            SimConfig::new().rpm(800.0),
+            SimConfig::new().points_per_sector(0),
+            SimConfig::new().points_per_sector(MAX_MEASUREMENTS + 1),
+            SimConfig::new().noise(NoiseModel { range_sigma_mm: -1.0, ..NoiseModel::default() }),
+            SimConfig::new().noise(NoiseModel { dropout_probability: 1.5, ..NoiseModel::default() }),
+            SimConfig::new().noise(NoiseModel { min_range_mm: 100.0, max_range_mm: 100.0, ..NoiseModel::default() }),
+        ] {
+            assert!(Simulator::new(circle(), Trajectory::fixed(SensorPose::default()), config).is_err());
+        }
+    }
+
+    #[test]
+    fn transport_speed_must_be_zero_or_more() {
+        for speed in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(simulator(circle(), NoiseModel::ideal()).into_transport(speed).is_err());
+        }
+        // far too slow for a frame to ever be due, which isn't an error
+        let mut transport = simulator(circle(), NoiseModel::ideal()).into_transport(1e-300).unwrap();
+        assert_eq!(transport.read(&mut [0u8; 64]).unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        let mut transport = simulator(circle(), NoiseModel::ideal()).into_transport(0.0).unwrap();
+        assert!(transport.read(&mut [0u8; 64]).unwrap() > 0);
+    }
+}
@@ -0,0 +1,305 @@
+// Where the Lidar reads its bytes from: a serial port, a network bridge (e.g. ser2net, or an ESP32
+// forwarding the UART over wifi), a Unix socket, a recording, or anything else which implements Read.
+//
+// Endpoints are given as URIs:
+//   /dev/ttyUSB0, COM3                        serial port (anything without a scheme)
+//   serial:///dev/ttyUSB0?baud=230400         serial port, overriding the configured baud rate
+//   tcp://192.168.1.50:4000                   TCP client
+//   udp://0.0.0.0:4000                        UDP datagrams received on a local address
+//   unix:///tmp/lidar.sock                    Unix socket client
+//   file://capture.bin?speed=1.0              recording, see replay.rs (file:///abs/path for absolute paths)
+use crate::capture::{is_capture_header, CaptureReader};
+use crate::config::LidarConfig;
+use crate::error::{Error, Result};
+use crate::protocol::get_nanos;
+use crate::replay::{CaptureReplay, ReplaySource};
+use crate::sim::SimTransport;
+
+use serialport::SerialPort;
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{TcpStream, UdpSocket};
+
+// A source of bytes for the Lidar worker thread.
+// Reads should time out (with io::ErrorKind::TimedOut) every so often if no data arrives,
+// and return Ok(0) when the source has ended.
+pub trait Transport: Read + Send {
+    // unix epoch nanoseconds when the bytes most recently read were received
+    fn timestamp(&self) -> u128 {
+        get_nanos()
+    }
+
+    // whether reaching the end of the data is expected (e.g. a file), rather than a lost connection
+    fn finite(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for Box<dyn SerialPort> {}
+
+impl<R: Read + Send> Transport for ReplaySource<R> {
+    fn finite(&self) -> bool {
+        true
+    }
+}
+
+impl<R: Read + Send> Transport for CaptureReplay<R> {
+    fn timestamp(&self) -> u128 {
+        CaptureReplay::timestamp(self)
+    }
+
+    fn finite(&self) -> bool {
+        true
+    }
+}
+
+impl Transport for SimTransport {
+    fn timestamp(&self) -> u128 {
+        SimTransport::timestamp(self)
+    }
+
+    fn finite(&self) -> bool {
+        SimTransport::finite(self)
+    }
+}
+
+// Wraps any reader of live data, e.g. a pipe from another process.
+// The reader should time out regularly if possible, otherwise Lidar::close can only return once data arrives.
+pub struct ReadTransport<R>(pub R);
+
+impl<R: Read> Read for ReadTransport<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read + Send> Transport for ReadTransport<R> {}
+
+// Sockets report a read timeout as WouldBlock on some platforms, so this turns it into TimedOut
+pub struct StreamTransport<S>(S);
+
+impl<S: Read> Read for StreamTransport<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::Error::from(io::ErrorKind::TimedOut)),
+            r => r,
+        }
+    }
+}
+
+impl<S: Read + Send> Transport for StreamTransport<S> {}
+
+// Receives datagrams, handing out their contents like a stream
+pub struct UdpTransport {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+    length: usize,
+    position: usize,
+}
+
+impl Read for UdpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.length {
+            // receive into our own buffer, as the caller's may be smaller than a datagram
+            self.length = match self.socket.recv(&mut self.buffer) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Err(io::Error::from(io::ErrorKind::TimedOut)),
+                r => r?,
+            };
+            self.position = 0;
+        }
+
+        let n = buf.len().min(self.length - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        // an empty datagram isn't the end of the stream
+        if n == 0 && !buf.is_empty() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        Ok(n)
+    }
+}
+
+impl Transport for UdpTransport {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Serial { path: String, baud: Option<u32> }, // None uses the configured baud rate
+    Tcp(String), // host:port to connect to
+    Udp(String), // local host:port to receive on
+    Unix(String), // socket path
+    File { path: String, speed: f64 },
+}
+
+impl Endpoint {
+    // parses a URI, as listed at the top of this file
+    pub fn parse(uri: &str) -> Result<Endpoint> {
+        let (scheme, rest) = match uri.split_once("://") {
+            Some((scheme, rest)) => (scheme, rest),
+            None => return Ok(Endpoint::Serial { path: uri.to_string(), baud: None }),
+        };
+        let (address, query) = match rest.split_once('?') {
+            Some((address, query)) => (address, Some(query)),
+            None => (rest, None),
+        };
+        if address.is_empty() {
+            return Err(Error::InvalidParameter(format!("no address given in '{}'", uri)));
+        }
+
+        let mut endpoint = match scheme {
+            "serial" => Endpoint::Serial { path: address.to_string(), baud: None },
+            "tcp" => Endpoint::Tcp(address.to_string()),
+            "udp" => Endpoint::Udp(address.to_string()),
+            "unix" => Endpoint::Unix(address.to_string()),
+            "file" => Endpoint::File { path: address.to_string(), speed: 1.0 },
+            s => return Err(Error::InvalidParameter(format!("unknown scheme '{}' in '{}'", s, uri))),
+        };
+
+        if let Endpoint::Tcp(address) | Endpoint::Udp(address) = &endpoint {
+            // host:port, or [ipv6]:port
+            let port = address.rsplit_once(':').map(|(_, port)| port);
+            if port.and_then(|p| p.parse::<u16>().ok()).is_none() {
+                return Err(Error::InvalidParameter(format!("no port given in '{}'", uri)));
+            }
+        }
+
+        for pair in query.into_iter().flat_map(|q| q.split('&')).filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let invalid = || Error::InvalidParameter(format!("invalid value '{}' for '{}' in '{}'", value, key, uri));
+
+            match (&mut endpoint, key) {
+                (Endpoint::Serial { baud, .. }, "baud") => *baud = Some(value.parse().map_err(|_| invalid())?),
+                (Endpoint::File { speed, .. }, "speed") => {
+                    *speed = value.parse().map_err(|_| invalid())?;
+                    if !(*speed >= 0.0 && speed.is_finite()) {
+                        return Err(invalid());
+                    }
+                },
+                _ => return Err(Error::InvalidParameter(format!("unknown option '{}' in '{}'", key, uri))),
+            }
+        }
+        Ok(endpoint)
+    }
+
+    pub fn open(&self, config: &LidarConfig) -> Result<Box<dyn Transport>> {
+        match self {
+            Endpoint::Serial { path, baud } => {
+                let mut port = serialport::new(path, baud.unwrap_or(config.baud_rate))
+                    .timeout(config.timeout)
+                    .parity(config.parity)
+                    .flow_control(config.flow_control)
+                    .open()?;
+                if let Some(level) = config.dtr {
+                    port.write_data_terminal_ready(level)?;
+                }
+                if let Some(level) = config.rts {
+                    port.write_request_to_send(level)?;
+                }
+                Ok(Box::new(port))
+            },
+            Endpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_read_timeout(Some(config.timeout))?;
+                Ok(Box::new(StreamTransport(stream)))
+            },
+            Endpoint::Udp(address) => {
+                let socket = UdpSocket::bind(address)?;
+                socket.set_read_timeout(Some(config.timeout))?;
+                Ok(Box::new(UdpTransport { socket, buffer: vec![0; 65536], length: 0, position: 0 }))
+            },
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = std::os::unix::net::UnixStream::connect(path)?;
+                stream.set_read_timeout(Some(config.timeout))?;
+                Ok(Box::new(StreamTransport(stream)))
+            },
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(Error::InvalidParameter("unix sockets are not supported on this platform".to_string())),
+            Endpoint::File { path, speed } => {
+                // capture files have timestamps to replay, anything else is taken to be raw serial data
+                let mut file = BufReader::new(File::open(path)?);
+                if is_capture_header(file.fill_buf()?) {
+                    Ok(Box::new(CaptureReplay::new(CaptureReader::new(file)?, *speed)))
+                } else {
+                    Ok(Box::new(ReplaySource::new(file, *speed, config.baud_rate)))
+                }
+            },
+        }
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Serial { path, baud: Some(baud) } => f.write_str(&format!("serial://{}?baud={}", path, baud)),
+            Endpoint::Serial { path, baud: None } => f.write_str(&format!("serial://{}", path)),
+            Endpoint::Tcp(address) => f.write_str(&format!("tcp://{}", address)),
+            Endpoint::Udp(address) => f.write_str(&format!("udp://{}", address)),
+            Endpoint::Unix(path) => f.write_str(&format!("unix://{}", path)),
+            Endpoint::File { path, speed } => f.write_str(&format!("file://{}?speed={}", path, speed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serial(path: &str, baud: Option<u32>) -> Endpoint {
+        Endpoint::Serial { path: path.to_string(), baud }
+    }
+
+    #[test]
+    fn parses_every_scheme() {
+        for (uri, expected) in [
+            ("/dev/ttyUSB0", serial("/dev/ttyUSB0", None)),
+            ("COM3", serial("COM3", None)),
+            ("serial:///dev/ttyUSB0", serial("/dev/ttyUSB0", None)),
+            ("serial:///dev/ttyUSB0?baud=230400", serial("/dev/ttyUSB0", Some(230400))),
+            ("tcp://192.168.1.50:4000", Endpoint::Tcp("192.168.1.50:4000".to_string())),
+            ("tcp://lidar.local:4000", Endpoint::Tcp("lidar.local:4000".to_string())),
+            ("tcp://[::1]:4000", Endpoint::Tcp("[::1]:4000".to_string())),
+            ("udp://0.0.0.0:4000", Endpoint::Udp("0.0.0.0:4000".to_string())),
+            ("unix:///tmp/lidar.sock", Endpoint::Unix("/tmp/lidar.sock".to_string())),
+            ("file://capture.d2", Endpoint::File { path: "capture.d2".to_string(), speed: 1.0 }),
+            ("file://capture.d2?speed=0", Endpoint::File { path: "capture.d2".to_string(), speed: 0.0 }),
+            ("file:///data/capture.bin?speed=2.5", Endpoint::File { path: "/data/capture.bin".to_string(), speed: 2.5 }),
+        ] {
+            assert_eq!(Endpoint::parse(uri).unwrap(), expected, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for uri in ["serial:///dev/ttyUSB0?baud=230400", "tcp://192.168.1.50:4000", "udp://0.0.0.0:4000", "unix:///tmp/lidar.sock", "file://capture.d2?speed=2"] {
+            assert_eq!(Endpoint::parse(uri).unwrap().to_string(), uri);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_uris() {
+        for uri in [
+            // missing address or port
+            "tcp://",
+            "tcp://192.168.1.50",
+            "tcp://192.168.1.50:",
+            "udp://0.0.0.0:port",
+            "udp://0.0.0.0:70000",
+            "serial://?baud=115200",
+            // unknown scheme or option
+            "http://192.168.1.50:4000",
+            "tcp://192.168.1.50:4000?baud=115200",
+            "file://capture.d2?baud=115200",
+            // bad values
+            "serial:///dev/ttyUSB0?baud=fast",
+            "serial:///dev/ttyUSB0?baud=-1",
+            "serial:///dev/ttyUSB0?baud",
+            "file://capture.d2?speed=-1",
+            "file://capture.d2?speed=inf",
+            "file://capture.d2?speed=nan",
+        ] {
+            assert!(matches!(Endpoint::parse(uri), Err(Error::InvalidParameter(_))), "{}", uri);
+        }
+    }
+}
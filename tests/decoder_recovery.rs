@@ -0,0 +1,149 @@
+// Measures how many frames the FrameDecoder recovers from a stream with injected corruption,
+// compared to the original one-frame-at-a-time PartialFrame state machine.
+//
+// Fails if a corrupted frame is ever emitted, or if less than 99.5% of intact frames are recovered.
+// (100% isn't always possible: if a frame is truncated right before its last CRC byte, and that byte happened
+// to be 0xAA, the header of the following frame completes it - and is then gone.)
+// cargo test --release --test decoder_recovery -- --nocapture prints the rates.
+use std::collections::HashSet;
+use std::io::Write;
+
+use delta2_lidar_rs::emulator::XorShift;
+use delta2_lidar_rs::frame::RawFrame;
+use delta2_lidar_rs::protocol::{DecoderStats, FrameDecoder, Measurement, PartialFrame, ZERO_ANGLE_OFFSET};
+
+// builds a measurement frame, with the sequence number hidden in the first distance so every frame is unique
+fn measurement_frame(sequence: usize) -> Vec<u8> {
+    let measurements = (0..28)
+        .map(|i| {
+            let distance = if i == 0 { sequence as u16 } else { (1000 + i * 40) as u16 };
+            Measurement { angle: 0.0, signal_quality: 200, distance_mm: distance as f32 * 0.25 }
+        })
+        .collect::<Vec<_>>();
+    let start_angle = ((sequence % 15) * 24) as f32 + ZERO_ANGLE_OFFSET;
+
+    RawFrame::encode_measurements(300.0, start_angle, &measurements, 0).unwrap().as_bytes().to_vec()
+}
+
+fn corrupt(rng: &mut XorShift, frame: &mut Vec<u8>) {
+    let i = rng.below(frame.len());
+    match rng.below(4) {
+        // flip some bits
+        0 => frame[i] ^= 1 << rng.below(8),
+        // drop a byte
+        1 => {
+            frame.remove(i);
+        }
+        // insert a burst of noise, which may contain fake headers
+        2 => {
+            for _ in 0..rng.below(12) + 1 {
+                let b = if rng.chance(0.3) { 0xAA } else { rng.next_u64() as u8 };
+                frame.insert(i, b);
+            }
+        }
+        // truncate the frame
+        _ => frame.truncate(i),
+    }
+}
+
+// the original decoding approach: one PartialFrame, reset on any bad byte, and skip frames with a bad CRC
+fn decode_legacy(stream: &[u8]) -> Vec<Vec<u8>> {
+    let mut frames = vec![];
+    let mut frame = PartialFrame::new();
+
+    for b in stream {
+        let _ = frame.write(&[*b]);
+        if frame.bytes_wanted == 0 {
+            if frame.finished() {
+                frames.push(frame.data.clone());
+            }
+            frame.reset();
+        }
+    }
+    frames
+}
+
+fn decode(stream: &[u8], rng: &mut XorShift) -> (Vec<Vec<u8>>, DecoderStats) {
+    let mut frames = vec![];
+    let mut decoder = FrameDecoder::new();
+
+    // push in randomly sized chunks, like a serial port would
+    let mut rest = stream;
+    while !rest.is_empty() {
+        let n = (rng.below(300) + 1).min(rest.len());
+        frames.extend(decoder.push(&rest[..n]).map(|f| f.data));
+        rest = &rest[n..];
+    }
+    (frames, decoder.stats().clone())
+}
+
+struct Recovery {
+    intact: usize, // frames which made it through the corruption whole
+    legacy_good: usize,
+    good: usize,
+    bad: usize, // frames emitted which were never sent
+}
+
+impl Recovery {
+    fn rate(&self, n: usize) -> f64 {
+        100.0 * (n as f64) / (self.intact as f64)
+    }
+}
+
+fn run(num_frames: usize, probability: f64) -> Recovery {
+    let mut rng = XorShift(0x2545F4914F6CDD1D);
+
+    let mut stream: Vec<u8> = vec![];
+    let mut originals: HashSet<Vec<u8>> = HashSet::new();
+    let mut intact: HashSet<Vec<u8>> = HashSet::new();
+    let mut corrupted = 0;
+
+    for sequence in 0..num_frames {
+        let original = measurement_frame(sequence);
+        originals.insert(original.clone());
+        let mut frame = original.clone();
+        if rng.chance(probability) {
+            corrupt(&mut rng, &mut frame);
+            corrupted += 1;
+        }
+        // noise inserted just before or after a frame leaves it intact
+        if frame.windows(original.len()).any(|w| w == original.as_slice()) {
+            intact.insert(original);
+        }
+        stream.extend(frame);
+    }
+
+    let legacy = decode_legacy(&stream);
+    let (decoded, stats) = decode(&stream, &mut rng);
+
+    // (intact frames recovered, frames which don't match anything that was sent)
+    let count = |frames: &Vec<Vec<u8>>| -> (usize, usize) {
+        let good = frames.iter().filter(|f| intact.contains(*f)).count();
+        let bad = frames.iter().filter(|f| !originals.contains(*f)).count();
+        (good, bad)
+    };
+    let (legacy_good, _) = count(&legacy);
+    let (good, bad) = count(&decoded);
+    let recovery = Recovery { intact: intact.len(), legacy_good, good, bad };
+
+    println!("{} frames, {} corrupted ({:.0}%), {} bytes", num_frames, corrupted, 100.0 * probability, stream.len());
+    println!("legacy PartialFrame : {} / {} intact frames recovered ({:.2}%)", legacy_good, recovery.intact, recovery.rate(legacy_good));
+    println!("FrameDecoder        : {} / {} intact frames recovered ({:.2}%), {} bad frames", good, recovery.intact, recovery.rate(good), bad);
+    println!("FrameDecoder stats  : {}", stats);
+    recovery
+}
+
+#[test]
+fn recovers_intact_frames() {
+    let recovery = run(10000, 0.2);
+    assert_eq!(recovery.bad, 0, "emitted corrupted frames");
+    assert!(recovery.rate(recovery.good) >= 99.5, "only recovered {:.2}% of intact frames", recovery.rate(recovery.good));
+    assert!(recovery.good > recovery.legacy_good);
+}
+
+#[test]
+fn recovers_intact_frames_from_heavy_corruption() {
+    let recovery = run(5000, 0.8);
+    assert_eq!(recovery.bad, 0, "emitted corrupted frames");
+    assert!(recovery.rate(recovery.good) >= 99.5, "only recovered {:.2}% of intact frames", recovery.rate(recovery.good));
+}
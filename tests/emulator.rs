@@ -0,0 +1,39 @@
+// Reads the emulator through the driver, as if it were a lidar on a serial port.
+#![cfg(target_os = "linux")]
+use std::thread;
+use std::time::{Duration, Instant};
+
+use delta2_lidar_rs::emulator::{EmulatorConfig, PtyEmulator};
+use delta2_lidar_rs::lidar::Lidar;
+
+#[test]
+fn lidar_reads_full_scans_from_the_emulator() {
+    let mut emulator = PtyEmulator::start(EmulatorConfig::new().rpm(600.0).points_per_sector(20)).unwrap();
+
+    let mut lidar = Lidar::new();
+    lidar.open(emulator.path().to_string()).unwrap();
+    // the first scan can start part-way through a revolution
+    for _ in 0..3 {
+        let scan = lidar.recv_fullscan_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!scan.frames.is_empty());
+        for frame in scan.frames.iter() {
+            assert_eq!(frame.rpm, 600.0);
+            assert_eq!(frame.measurements.len(), 20);
+        }
+    }
+    assert_eq!(lidar.stats().crc_failures, 0);
+
+    lidar.close().unwrap();
+    emulator.stop();
+}
+
+#[test]
+fn emulator_stops_while_nobody_is_reading() {
+    // fast enough to fill the pty well before it's stopped
+    let mut emulator = PtyEmulator::start(EmulatorConfig::new().rpm(765.0).sectors(1000).points_per_sector(128)).unwrap();
+    thread::sleep(Duration::from_millis(500));
+
+    let started = Instant::now();
+    emulator.stop();
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
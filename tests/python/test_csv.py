@@ -0,0 +1,47 @@
+# Tests for the python CSV functions, run against the installed package:
+#   python -m unittest discover -s tests/python
+import os
+import tempfile
+import unittest
+
+from delta2_lidar import from_csv, to_csv
+
+HEADER = "timestamp,frame,angle,distance_mm,quality,x,y"
+
+
+class CsvTest(unittest.TestCase):
+    def setUp(self):
+        self.dir = tempfile.TemporaryDirectory()
+        source = self.path("source.csv")
+        with open(source, "w") as f:
+            f.write(HEADER + "\n5,0,10,1500,186,0.26,1.48\n5,0,12,1600,180,0.33,1.57\n")
+        self.frames = from_csv(source)
+
+    def tearDown(self):
+        self.dir.cleanup()
+
+    def path(self, name):
+        return os.path.join(self.dir.name, name)
+
+    def test_round_trip(self):
+        to_csv(self.path("out.csv"), self.frames)
+        frames = from_csv(self.path("out.csv"))
+        self.assertEqual(len(frames), 1)
+        self.assertEqual([m.distance_mm for m in frames[0].measurements], [1500.0, 1600.0])
+
+    def test_rejected_item_leaves_no_file(self):
+        with self.assertRaises(TypeError):
+            to_csv(self.path("out.csv"), [self.frames[0], 1])
+        self.assertFalse(os.path.exists(self.path("out.csv")))
+
+    def test_rejected_item_leaves_an_existing_file_alone(self):
+        with open(self.path("out.csv"), "w") as f:
+            f.write("precious")
+        with self.assertRaises(TypeError):
+            to_csv(self.path("out.csv"), [self.frames[0], "not a frame"])
+        with open(self.path("out.csv")) as f:
+            self.assertEqual(f.read(), "precious")
+
+
+if __name__ == "__main__":
+    unittest.main()